@@ -0,0 +1,93 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What to do when a media item would be written to a path that already
+/// belongs to a different item, like two phones that both named a photo
+/// `IMG_0001.JPG` on the same day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// write it to the first free `IMG_0001~2.JPG`, `IMG_0001~3.JPG`, ...
+    #[default]
+    Suffix,
+    /// leave the existing file alone and don't download the new one
+    Skip,
+    /// replace the existing file with the new one
+    Overwrite,
+    /// write it to `IMG_0001_<media id>.JPG`
+    IdInName,
+}
+
+impl CollisionPolicy {
+    pub const VARIANTS: &'static [&'static str] = &["suffix", "skip", "overwrite", "id-in-name"];
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> std::result::Result<CollisionPolicy, String> {
+        match policy {
+            "suffix" => Ok(CollisionPolicy::Suffix),
+            "skip" => Ok(CollisionPolicy::Skip),
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "id-in-name" => Ok(CollisionPolicy::IdInName),
+            _ => Err(format!("unknown collision policy {}", policy)),
+        }
+    }
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let policy = match self {
+            CollisionPolicy::Suffix => "suffix",
+            CollisionPolicy::Skip => "skip",
+            CollisionPolicy::Overwrite => "overwrite",
+            CollisionPolicy::IdInName => "id-in-name",
+        };
+        write!(f, "{}", policy)
+    }
+}
+
+/// Adds `suffix` to the end of the file name in `path`, ahead of its
+/// extension.
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::path::{Path, PathBuf};
+
+    use super::{with_suffix, CollisionPolicy};
+
+    #[test]
+    fn test_with_suffix() {
+        assert_eq!(
+            PathBuf::from("2014/10/02/IMG_0001~2.JPG"),
+            with_suffix(Path::new("2014/10/02/IMG_0001.JPG"), "~2")
+        );
+        assert_eq!(
+            PathBuf::from("2014/10/02/scan_abc123"),
+            with_suffix(Path::new("2014/10/02/scan"), "_abc123")
+        );
+        assert_eq!(
+            PathBuf::from("archive.tar~2.gz"),
+            with_suffix(Path::new("archive.tar.gz"), "~2")
+        );
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        for variant in CollisionPolicy::VARIANTS {
+            let policy: CollisionPolicy = variant.parse().unwrap();
+            assert_eq!(*variant, policy.to_string());
+        }
+        assert!("rename".parse::<CollisionPolicy>().is_err());
+    }
+}
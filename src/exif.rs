@@ -0,0 +1,784 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use crate::{partial_path, Error, Media, Result, TimeZone};
+
+const SOI: u8 = 0xd8;
+const SOS: u8 = 0xda;
+const EOI: u8 = 0xd9;
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+/// The most a segment can hold once its two length bytes are counted.
+const MAX_SEGMENT: usize = 0xffff - 2;
+/// A big endian TIFF structure with an empty first IFD, for JPEGs that have no
+/// EXIF block of their own.
+const EMPTY_TIFF: &[u8] = b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0";
+
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const UNDEFINED: u16 = 7;
+
+const IMAGE_DESCRIPTION: u16 = 0x010e;
+const MAKE: u16 = 0x010f;
+const MODEL: u16 = 0x0110;
+const DATE_TIME: u16 = 0x0132;
+const EXIF_IFD: u16 = 0x8769;
+const EXPOSURE_TIME: u16 = 0x829a;
+const F_NUMBER: u16 = 0x829d;
+const ISO_SPEED: u16 = 0x8827;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const DATE_TIME_DIGITIZED: u16 = 0x9004;
+const OFFSET_TIME: u16 = 0x9010;
+const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const OFFSET_TIME_DIGITIZED: u16 = 0x9012;
+const FOCAL_LENGTH: u16 = 0x920a;
+const USER_COMMENT: u16 = 0x9286;
+const PIXEL_X_DIMENSION: u16 = 0xa002;
+const PIXEL_Y_DIMENSION: u16 = 0xa003;
+
+/// Writes the creation time, description and camera that Google knows about
+/// the media into the EXIF block of the JPEG at `path`, keeping every other
+/// tag the camera wrote. Times are written as the wall clock time in
+/// `timezone`, along with its offset. Only the metadata segments are rewritten, the image
+/// data is copied as is. Returns false, leaving the file alone, when it is not
+/// a JPEG.
+pub(crate) fn embed(path: &Path, media: &Media, timezone: TimeZone) -> Result<bool> {
+    let jpeg = fs::read(path).map_err(|err| Error::io(path, err))?;
+    let rewritten = match rewrite(&jpeg, media, timezone)? {
+        Some(rewritten) => rewritten,
+        None => return Ok(false),
+    };
+    let partial = partial_path(path);
+    fs::write(&partial, rewritten).map_err(|err| Error::io(&partial, err))?;
+    fs::rename(&partial, path).map_err(|err| Error::io(path, err))?;
+    Ok(true)
+}
+
+/// A marker segment before the image data, as the range of bytes it spans
+/// including its marker.
+struct Segment {
+    marker: u8,
+    start: usize,
+    end: usize,
+}
+
+fn rewrite(jpeg: &[u8], media: &Media, timezone: TimeZone) -> Result<Option<Vec<u8>>> {
+    let (segments, image_data) = match segments(jpeg) {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    let existing = segments
+        .iter()
+        .find(|segment| is_exif(jpeg, segment))
+        .map(|segment| &jpeg[segment.start + 4 + EXIF_HEADER.len()..segment.end]);
+    // a block that can't be read is replaced rather than patched
+    let tiff = match existing.and_then(|existing| tiff(existing, media, timezone)) {
+        Some(tiff) => tiff,
+        None => tiff(EMPTY_TIFF, media, timezone).expect("the empty TIFF structure is valid"),
+    };
+
+    let mut app1 = EXIF_HEADER.to_vec();
+    app1.extend(tiff);
+    if app1.len() > MAX_SEGMENT {
+        return Ok(None);
+    }
+
+    let mut rewritten = Vec::with_capacity(jpeg.len() + app1.len());
+    rewritten.extend([0xff, SOI]);
+    let mut rest = segments.iter().peekable();
+    // JFIF wants its APP0 segment to come first
+    if let Some(app0) = rest.next_if(|segment| segment.marker == APP0) {
+        rewritten.extend(&jpeg[app0.start..app0.end]);
+    }
+    rewritten.extend([0xff, APP1]);
+    rewritten.extend(((app1.len() + 2) as u16).to_be_bytes());
+    rewritten.extend(app1);
+    for segment in rest.filter(|segment| !is_exif(jpeg, segment)) {
+        rewritten.extend(&jpeg[segment.start..segment.end]);
+    }
+    rewritten.extend(&jpeg[image_data..]);
+    Ok(Some(rewritten))
+}
+
+/// Splits a JPEG into the segments that come before the image data and the
+/// offset that data starts at, or None if it isn't a JPEG.
+fn segments(jpeg: &[u8]) -> Option<(Vec<Segment>, usize)> {
+    if jpeg.get(0..2)? != [0xff, SOI] {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut i = 2;
+    loop {
+        if *jpeg.get(i)? != 0xff {
+            return None;
+        }
+        // any number of 0xff bytes may pad out a marker
+        while *jpeg.get(i + 1)? == 0xff {
+            i += 1;
+        }
+        let marker = jpeg[i + 1];
+        if marker == SOS || marker == EOI {
+            return Some((segments, i));
+        }
+        let len = u16::from_be_bytes([*jpeg.get(i + 2)?, *jpeg.get(i + 3)?]) as usize;
+        let end = i + 2 + len;
+        if len < 2 || end > jpeg.len() {
+            return None;
+        }
+        segments.push(Segment {
+            marker,
+            start: i,
+            end,
+        });
+        i = end;
+    }
+}
+
+fn is_exif(jpeg: &[u8], segment: &Segment) -> bool {
+    segment.marker == APP1 && jpeg[segment.start + 4..segment.end].starts_with(EXIF_HEADER)
+}
+
+/// The byte order of a TIFF structure, which everything added to it follows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    fn u16(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+        }
+    }
+
+    fn u16_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Big => value.to_be_bytes(),
+            ByteOrder::Little => value.to_le_bytes(),
+        }
+    }
+
+    fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::Big => value.to_be_bytes(),
+            ByteOrder::Little => value.to_le_bytes(),
+        }
+    }
+}
+
+/// Reads the IFDs of an existing TIFF structure.
+struct Reader<'a> {
+    tiff: &'a [u8],
+    order: ByteOrder,
+}
+
+impl<'a> Reader<'a> {
+    fn new(tiff: &'a [u8]) -> Option<Reader<'a>> {
+        let order = match tiff.get(0..2)? {
+            b"MM" => ByteOrder::Big,
+            b"II" => ByteOrder::Little,
+            _ => return None,
+        };
+        Some(Reader { tiff, order })
+    }
+
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        Some(self.order.u16(self.tiff.get(at..at + 2)?.try_into().ok()?))
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        Some(self.order.u32(self.tiff.get(at..at + 4)?.try_into().ok()?))
+    }
+
+    /// The entries of the IFD at `offset` and the offset of the IFD after it.
+    fn ifd(&self, offset: u32) -> Option<(Vec<Entry>, u32)> {
+        let offset = offset as usize;
+        let count = self.u16_at(offset)? as usize;
+        let entries = (0..count)
+            .map(|n| {
+                let at = offset + 2 + n * 12;
+                let bytes: [u8; 12] = self.tiff.get(at..at + 12)?.try_into().ok()?;
+                Some(Entry {
+                    tag: self.order.u16([bytes[0], bytes[1]]),
+                    bytes,
+                })
+            })
+            .collect::<Option<Vec<Entry>>>()?;
+        let next = self.u32_at(offset + 2 + count * 12)?;
+        Some((entries, next))
+    }
+}
+
+/// An IFD entry as the camera wrote it. Values that don't fit in the entry
+/// are stored elsewhere in the structure at an offset the entry holds, which
+/// stays valid as long as the original bytes are kept in place.
+struct Entry {
+    tag: u16,
+    bytes: [u8; 12],
+}
+
+/// A tag and its value, encoded in the byte order of the structure.
+struct Field {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+impl Field {
+    fn ascii(tag: u16, value: &str) -> Field {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        Field {
+            tag,
+            kind: ASCII,
+            count: bytes.len() as u32,
+            value: bytes,
+        }
+    }
+
+    fn short(order: ByteOrder, tag: u16, value: u16) -> Field {
+        Field {
+            tag,
+            kind: SHORT,
+            count: 1,
+            value: order.u16_bytes(value).to_vec(),
+        }
+    }
+
+    fn long(order: ByteOrder, tag: u16, value: u32) -> Field {
+        Field {
+            tag,
+            kind: LONG,
+            count: 1,
+            value: order.u32_bytes(value).to_vec(),
+        }
+    }
+
+    fn rational(order: ByteOrder, tag: u16, (numerator, denominator): (u32, u32)) -> Field {
+        let mut value = order.u32_bytes(numerator).to_vec();
+        value.extend(order.u32_bytes(denominator));
+        Field {
+            tag,
+            kind: RATIONAL,
+            count: 1,
+            value,
+        }
+    }
+
+    /// A user comment in UCS-2, for text that an ASCII field can't hold.
+    fn unicode_comment(order: ByteOrder, tag: u16, comment: &str) -> Field {
+        let mut value = b"UNICODE\0".to_vec();
+        for unit in comment.encode_utf16() {
+            value.extend(order.u16_bytes(unit));
+        }
+        Field {
+            tag,
+            kind: UNDEFINED,
+            count: value.len() as u32,
+            value,
+        }
+    }
+}
+
+/// An entry of an IFD being written.
+enum Item {
+    Kept(Entry),
+    Added(Field),
+}
+
+impl Item {
+    fn tag(&self) -> u16 {
+        match self {
+            Item::Kept(entry) => entry.tag,
+            Item::Added(field) => field.tag,
+        }
+    }
+}
+
+/// How the fields of one IFD change.
+#[derive(Default)]
+struct Changes {
+    /// written whether or not the camera wrote them
+    replace: Vec<Field>,
+    /// written only when the camera didn't
+    fill: Vec<Field>,
+    /// dropped from what the camera wrote
+    remove: Vec<u16>,
+}
+
+impl Changes {
+    fn apply(self, entries: Vec<Entry>) -> Vec<Item> {
+        let replaced: Vec<u16> = self.replace.iter().map(|field| field.tag).collect();
+        let present: Vec<u16> = entries.iter().map(|entry| entry.tag).collect();
+        let mut items: Vec<Item> = entries
+            .into_iter()
+            .filter(|entry| !replaced.contains(&entry.tag) && !self.remove.contains(&entry.tag))
+            .map(Item::Kept)
+            .collect();
+        items.extend(self.replace.into_iter().map(Item::Added));
+        items.extend(
+            self.fill
+                .into_iter()
+                .filter(|field| !present.contains(&field.tag))
+                .map(Item::Added),
+        );
+        items
+    }
+}
+
+/// Patches an existing TIFF structure with the media's metadata, or None if
+/// it can't be read. The original bytes are kept as they are and new copies
+/// of the first IFD and the EXIF sub-IFD are appended, so every value the
+/// camera wrote elsewhere, such as its maker notes and the thumbnail in the
+/// second IFD, stays at the offset that points to it.
+fn tiff(existing: &[u8], media: &Media, timezone: TimeZone) -> Option<Vec<u8>> {
+    let reader = Reader::new(existing)?;
+    let order = reader.order;
+    let (ifd0, ifd1) = reader.ifd(reader.u32_at(4)?)?;
+    let exif_offset = ifd0
+        .iter()
+        .find(|entry| entry.tag == EXIF_IFD)
+        .map(|entry| order.u32(entry.bytes[8..12].try_into().unwrap()));
+    let (exif, exif_next) = match exif_offset {
+        Some(offset) => reader.ifd(offset)?,
+        None => (Vec::new(), 0),
+    };
+
+    let (ifd0_changes, exif_changes) = changes(media, order, timezone);
+    let mut ifd0 = ifd0_changes.apply(ifd0);
+    ifd0.retain(|item| item.tag() != EXIF_IFD);
+    let exif = exif_changes.apply(exif);
+
+    let mut tiff = existing.to_vec();
+    // IFDs start on a word boundary
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let ifd0_offset = tiff.len();
+    // the pointer's value is only known once the size of IFD0 is, but adding
+    // it doesn't change that size since it fits in the entry
+    ifd0.push(Item::Added(Field::long(order, EXIF_IFD, 0)));
+    let exif_offset = ifd0_offset + ifd_len(&ifd0);
+    if let Some(Item::Added(pointer)) = ifd0.last_mut() {
+        pointer.value = order.u32_bytes(exif_offset as u32).to_vec();
+    }
+    write_ifd(&mut tiff, order, ifd0, ifd1);
+    write_ifd(&mut tiff, order, exif, exif_next);
+    tiff[4..8].copy_from_slice(&order.u32_bytes(ifd0_offset as u32));
+    Some(tiff)
+}
+
+/// What Google knows about the media, as changes to the first IFD and the
+/// EXIF sub-IFD. The date, description and camera replace what the camera
+/// wrote, while the exposure and dimensions only fill in for tags it left out.
+fn changes(media: &Media, order: ByteOrder, timezone: TimeZone) -> (Changes, Changes) {
+    let metadata = &media.media_metadata;
+    // Google records creation times in UTC, but most readers ignore the offset
+    // tags and show the time as is, so it is written as the local time. Times
+    // that can't be parsed are left out.
+    let created_on = media.created_on().ok().map(|created_on| {
+        (
+            timezone
+                .from_utc(created_on)
+                .format("%Y:%m:%d %H:%M:%S")
+                .to_string(),
+            timezone.offset(created_on).to_string(),
+        )
+    });
+    let (make, model) = metadata.camera();
+
+    let mut ifd0 = Changes::default();
+    let mut exif = Changes::default();
+    if let Some(description) = media.description.as_deref().filter(|d| !d.is_empty()) {
+        if description.is_ascii() {
+            ifd0.replace
+                .push(Field::ascii(IMAGE_DESCRIPTION, description));
+        } else {
+            // ImageDescription is ASCII only, so the camera's is dropped
+            // rather than left to contradict the comment
+            ifd0.remove.push(IMAGE_DESCRIPTION);
+            exif.replace
+                .push(Field::unicode_comment(order, USER_COMMENT, description));
+        }
+    }
+    if let Some(make) = make {
+        ifd0.replace.push(Field::ascii(MAKE, make));
+    }
+    if let Some(model) = model {
+        ifd0.replace.push(Field::ascii(MODEL, model));
+    }
+    if let Some((created_on, offset)) = &created_on {
+        ifd0.replace.push(Field::ascii(DATE_TIME, created_on));
+        exif.replace
+            .push(Field::ascii(DATE_TIME_ORIGINAL, created_on));
+        exif.replace
+            .push(Field::ascii(DATE_TIME_DIGITIZED, created_on));
+        for offset_time in [OFFSET_TIME, OFFSET_TIME_ORIGINAL, OFFSET_TIME_DIGITIZED] {
+            exif.replace.push(Field::ascii(offset_time, offset));
+        }
+    }
+
+    if let Some(photo) = &metadata.photo {
+        let exposure = photo
+            .exposure_time
+            .as_deref()
+            .and_then(|time| time.trim_end_matches('s').parse::<f64>().ok());
+        if let Some(exposure) = exposure.filter(|exposure| *exposure > 0.0) {
+            exif.fill.push(Field::rational(
+                order,
+                EXPOSURE_TIME,
+                exposure_time(exposure),
+            ));
+        }
+        if let Some(f_number) = photo.aperture_f_number {
+            exif.fill
+                .push(Field::rational(order, F_NUMBER, rational(f_number)));
+        }
+        if let Some(iso) = photo.iso_equivalent {
+            exif.fill.push(Field::short(
+                order,
+                ISO_SPEED,
+                iso.min(u16::MAX as u32) as u16,
+            ));
+        }
+        if let Some(focal_length) = photo.focal_length {
+            exif.fill
+                .push(Field::rational(order, FOCAL_LENGTH, rational(focal_length)));
+        }
+    }
+    if let Some(width) = metadata.width {
+        exif.fill.push(Field::long(
+            order,
+            PIXEL_X_DIMENSION,
+            width.min(u32::MAX as u64) as u32,
+        ));
+    }
+    if let Some(height) = metadata.height {
+        exif.fill.push(Field::long(
+            order,
+            PIXEL_Y_DIMENSION,
+            height.min(u32::MAX as u64) as u32,
+        ));
+    }
+    (ifd0, exif)
+}
+
+/// The bytes an IFD takes up, including the values that don't fit in their
+/// entries.
+fn ifd_len(items: &[Item]) -> usize {
+    let overflow: usize = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Added(field) if field.value.len() > 4 => Some(field.value.len()),
+            _ => None,
+        })
+        .map(|len| len + len % 2)
+        .sum();
+    2 + items.len() * 12 + 4 + overflow
+}
+
+/// Appends an IFD to `tiff`, whose length is the offset the IFD starts at,
+/// followed by the values of its added fields that don't fit in their entries.
+/// Tags have to be written in ascending order.
+fn write_ifd(tiff: &mut Vec<u8>, order: ByteOrder, mut items: Vec<Item>, next: u32) {
+    items.sort_by_key(Item::tag);
+
+    let start = tiff.len();
+    let mut overflow = Vec::new();
+    let overflow_start = start + 2 + items.len() * 12 + 4;
+    tiff.extend(order.u16_bytes(items.len() as u16));
+    for item in items {
+        let field = match item {
+            Item::Kept(entry) => {
+                tiff.extend(entry.bytes);
+                continue;
+            }
+            Item::Added(field) => field,
+        };
+        tiff.extend(order.u16_bytes(field.tag));
+        tiff.extend(order.u16_bytes(field.kind));
+        tiff.extend(order.u32_bytes(field.count));
+        if field.value.len() <= 4 {
+            let mut value = field.value;
+            value.resize(4, 0);
+            tiff.extend(value);
+        } else {
+            let offset = overflow_start + overflow.len();
+            tiff.extend(order.u32_bytes(offset as u32));
+            overflow.extend(&field.value);
+            // values start on a word boundary
+            if overflow.len() % 2 == 1 {
+                overflow.push(0);
+            }
+        }
+    }
+    tiff.extend(order.u32_bytes(next));
+    tiff.extend(overflow);
+}
+
+/// Exposure times are conventionally written as a fraction of a second.
+fn exposure_time(seconds: f64) -> (u32, u32) {
+    if seconds < 1.0 {
+        (1, (1.0 / seconds).round() as u32)
+    } else {
+        rational(seconds)
+    }
+}
+
+fn rational(value: f64) -> (u32, u32) {
+    ((value * 1000.0).round() as u32, 1000)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::convert::TryInto;
+
+    use crate::{Media, MediaMetadata, Photo, TimeZone};
+
+    use super::{
+        exposure_time, rewrite, segments, write_ifd, ByteOrder, Field, Item, Reader, EXIF_HEADER,
+        RATIONAL, SHORT, UNDEFINED,
+    };
+
+    const FLASH: u16 = 0x9209;
+    const MAKER_NOTE: u16 = 0x927c;
+    const ORIENTATION: u16 = 0x0112;
+    const THUMBNAIL_OFFSET: u16 = 0x0201;
+    const THUMBNAIL: &[u8] = b"\xff\xd8thumbnail\xff\xd9";
+
+    /// The value of `tag` in the IFD at `offset`, as its raw bytes.
+    fn value(tiff: &[u8], offset: u32, tag: u16) -> Option<Vec<u8>> {
+        let reader = Reader::new(tiff)?;
+        let (entries, _) = reader.ifd(offset)?;
+        let entry = entries.into_iter().find(|entry| entry.tag == tag)?;
+        let kind = reader.order.u16([entry.bytes[2], entry.bytes[3]]);
+        let count = reader.order.u32(entry.bytes[4..8].try_into().unwrap()) as usize;
+        let len = count
+            * match kind {
+                SHORT => 2,
+                4 => 4,
+                RATIONAL => 8,
+                _ => 1,
+            };
+        if len <= 4 {
+            return Some(entry.bytes[8..8 + len].to_vec());
+        }
+        let at = reader.order.u32(entry.bytes[8..12].try_into().unwrap()) as usize;
+        Some(tiff[at..at + len].to_vec())
+    }
+
+    /// The offsets of the first IFD, the EXIF sub-IFD and the second IFD.
+    fn ifds(tiff: &[u8]) -> (u32, u32, u32) {
+        let reader = Reader::new(tiff).unwrap();
+        let ifd0 = reader.u32_at(4).unwrap();
+        let exif = reader.order.u32(
+            value(tiff, ifd0, super::EXIF_IFD).unwrap()[..4]
+                .try_into()
+                .unwrap(),
+        );
+        let (_, ifd1) = reader.ifd(ifd0).unwrap();
+        (ifd0, exif, ifd1)
+    }
+
+    /// What a camera might write: the orientation, its own make and date, a
+    /// flash setting, maker notes and a thumbnail, all little endian.
+    fn camera_tiff() -> Vec<u8> {
+        let order = ByteOrder::Little;
+        let mut tiff = b"II\x2a\0\x08\0\0\0".to_vec();
+        let ifd0 = vec![
+            Item::Added(Field::ascii(super::MAKE, "Canon")),
+            Item::Added(Field::short(order, ORIENTATION, 6)),
+            Item::Added(Field::ascii(super::DATE_TIME, "2014:10:02 08:01:23")),
+            Item::Added(Field::long(order, super::EXIF_IFD, 0)),
+        ];
+        let exif_offset = 8 + super::ifd_len(&ifd0);
+        let mut ifd0 = ifd0;
+        if let Some(Item::Added(pointer)) = ifd0.last_mut() {
+            pointer.value = order.u32_bytes(exif_offset as u32).to_vec();
+        }
+        let exif = vec![
+            Item::Added(Field::short(order, FLASH, 0x10)),
+            Item::Added(Field {
+                tag: MAKER_NOTE,
+                kind: UNDEFINED,
+                count: 10,
+                value: b"makernotes".to_vec(),
+            }),
+            Item::Added(Field::ascii(
+                super::DATE_TIME_ORIGINAL,
+                "2014:10:02 08:01:23",
+            )),
+        ];
+        let ifd1_offset = exif_offset + super::ifd_len(&exif);
+        let thumbnail_offset = ifd1_offset + 2 + 2 * 12 + 4;
+        let ifd1 = vec![
+            Item::Added(Field::long(
+                order,
+                THUMBNAIL_OFFSET,
+                thumbnail_offset as u32,
+            )),
+            Item::Added(Field::long(order, 0x0202, THUMBNAIL.len() as u32)),
+        ];
+        write_ifd(&mut tiff, order, ifd0, ifd1_offset as u32);
+        write_ifd(&mut tiff, order, exif, 0);
+        write_ifd(&mut tiff, order, ifd1, 0);
+        tiff.extend(THUMBNAIL);
+        tiff
+    }
+
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut exif = EXIF_HEADER.to_vec();
+        exif.extend(tiff);
+        let mut jpeg = vec![0xff, 0xd8];
+        jpeg.extend(b"\xff\xe0\0\x07JFIF\0");
+        jpeg.extend([0xff, 0xe1]);
+        jpeg.extend(((exif.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(&exif);
+        jpeg.extend(b"\xff\xdb\0\x04\x01\x02");
+        jpeg.extend(IMAGE_DATA);
+        jpeg
+    }
+
+    const IMAGE_DATA: &[u8] = b"\xff\xda\0\x04\x03\x04\xca\xfe\xba\xbe\xff\xd9";
+
+    /// The TIFF structure in the EXIF block of a rewritten JPEG.
+    fn rewritten_tiff(rewritten: &[u8]) -> Vec<u8> {
+        let (parsed, data_start) = segments(rewritten).unwrap();
+        let markers: Vec<u8> = parsed.iter().map(|segment| segment.marker).collect();
+        assert_eq!(vec![0xe0, 0xe1, 0xdb], markers);
+        assert_eq!(IMAGE_DATA, &rewritten[data_start..]);
+        let app1 = &parsed[1];
+        let exif = &rewritten[app1.start + 4..app1.end];
+        assert!(exif.starts_with(EXIF_HEADER));
+        exif[EXIF_HEADER.len()..].to_vec()
+    }
+
+    fn media(description: &str) -> Media {
+        Media {
+            id: String::from("abc123"),
+            description: Some(String::from(description)),
+            media_metadata: MediaMetadata {
+                creation_time: String::from("2014-10-02T15:01:23Z"),
+                width: Some(4032),
+                height: Some(3024),
+                photo: Some(Photo {
+                    camera_make: Some(String::from("Google")),
+                    camera_model: Some(String::from("Pixel 3")),
+                    exposure_time: Some(String::from("0.008s")),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn los_angeles() -> TimeZone {
+        "America/Los_Angeles".parse().unwrap()
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let rewritten = rewrite(&jpeg(&camera_tiff()), &media("at the lake"), los_angeles())
+            .unwrap()
+            .unwrap();
+        let tiff = rewritten_tiff(&rewritten);
+        assert_eq!(b"II", &tiff[..2]);
+        let (ifd0, exif, ifd1) = ifds(&tiff);
+
+        // what Google knows replaces what the camera wrote
+        assert_eq!(Some(b"Google\0".to_vec()), value(&tiff, ifd0, super::MAKE));
+        assert_eq!(
+            Some(b"Pixel 3\0".to_vec()),
+            value(&tiff, ifd0, super::MODEL)
+        );
+        assert_eq!(
+            Some(b"at the lake\0".to_vec()),
+            value(&tiff, ifd0, super::IMAGE_DESCRIPTION)
+        );
+        assert_eq!(
+            Some(b"2014:10:02 08:01:23\0".to_vec()),
+            value(&tiff, exif, super::DATE_TIME_ORIGINAL)
+        );
+        assert_eq!(
+            Some(b"-07:00\0".to_vec()),
+            value(&tiff, exif, super::OFFSET_TIME_ORIGINAL)
+        );
+        assert!(value(&tiff, exif, super::EXPOSURE_TIME).is_some());
+
+        // and everything else the camera wrote is kept
+        assert_eq!(Some(vec![6, 0]), value(&tiff, ifd0, ORIENTATION));
+        assert_eq!(Some(vec![0x10, 0]), value(&tiff, exif, FLASH));
+        assert_eq!(Some(b"makernotes".to_vec()), value(&tiff, exif, MAKER_NOTE));
+        let thumbnail = value(&tiff, ifd1, THUMBNAIL_OFFSET).unwrap();
+        let thumbnail = u32::from_le_bytes(thumbnail[..4].try_into().unwrap()) as usize;
+        assert_eq!(THUMBNAIL, &tiff[thumbnail..thumbnail + THUMBNAIL.len()]);
+    }
+
+    #[test]
+    fn test_rewrite_unicode_description() {
+        let rewritten = rewrite(&jpeg(&camera_tiff()), &media("Été à Paris"), los_angeles())
+            .unwrap()
+            .unwrap();
+        let tiff = rewritten_tiff(&rewritten);
+        let (ifd0, exif, _) = ifds(&tiff);
+        assert_eq!(None, value(&tiff, ifd0, super::IMAGE_DESCRIPTION));
+        let mut comment = b"UNICODE\0".to_vec();
+        for unit in "Été à Paris".encode_utf16() {
+            comment.extend(unit.to_le_bytes());
+        }
+        assert_eq!(Some(comment), value(&tiff, exif, super::USER_COMMENT));
+    }
+
+    #[test]
+    fn test_rewrite_without_exif() {
+        let mut jpeg = vec![0xff, 0xd8];
+        jpeg.extend(b"\xff\xe0\0\x07JFIF\0");
+        jpeg.extend(b"\xff\xdb\0\x04\x01\x02");
+        jpeg.extend(IMAGE_DATA);
+
+        let rewritten = rewrite(&jpeg, &media("at the lake"), TimeZone::Utc)
+            .unwrap()
+            .unwrap();
+        let tiff = rewritten_tiff(&rewritten);
+        assert_eq!(b"MM\0\x2a", &tiff[..4]);
+        let (ifd0, exif, ifd1) = ifds(&tiff);
+        assert_eq!(0, ifd1);
+        assert_eq!(
+            Some(b"2014:10:02 15:01:23\0".to_vec()),
+            value(&tiff, ifd0, super::DATE_TIME)
+        );
+        let exposure = value(&tiff, exif, super::EXPOSURE_TIME).unwrap();
+        assert_eq!(b"\0\0\0\x01\0\0\0\x7d", exposure.as_slice());
+    }
+
+    #[test]
+    fn test_rewrite_not_jpeg() {
+        let media = Media::default();
+        assert!(rewrite(b"\x89PNG\r\n\x1a\n", &media, TimeZone::Utc)
+            .unwrap()
+            .is_none());
+        assert!(rewrite(b"", &media, TimeZone::Utc).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_exposure_time() {
+        assert_eq!((1, 125), exposure_time(0.008));
+        assert_eq!((2500, 1000), exposure_time(2.5));
+    }
+}
@@ -0,0 +1,215 @@
+use serde_json::{json, Value};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A category Google sorts media into by their content, such as
+/// `screenshots` or `receipts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentCategory(&'static str);
+
+impl ContentCategory {
+    pub const VARIANTS: &'static [&'static str] = &[
+        "animals",
+        "arts",
+        "birthdays",
+        "cityscapes",
+        "crafts",
+        "documents",
+        "fashion",
+        "flowers",
+        "food",
+        "gardens",
+        "holidays",
+        "houses",
+        "landmarks",
+        "landscapes",
+        "night",
+        "people",
+        "performances",
+        "pets",
+        "receipts",
+        "screenshots",
+        "selfies",
+        "sport",
+        "travel",
+        "utility",
+        "weddings",
+        "whiteboards",
+    ];
+
+    /// The name the Photos Library API uses for the category.
+    fn api_name(&self) -> String {
+        self.0.to_uppercase()
+    }
+}
+
+impl FromStr for ContentCategory {
+    type Err = String;
+
+    fn from_str(category: &str) -> std::result::Result<ContentCategory, String> {
+        ContentCategory::VARIANTS
+            .iter()
+            .find(|variant| variant.eq_ignore_ascii_case(category))
+            .map(|variant| ContentCategory(variant))
+            .ok_or_else(|| format!("unknown content category {}", category))
+    }
+}
+
+impl fmt::Display for ContentCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Which kinds of media a search returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MediaType {
+    #[default]
+    All,
+    Photo,
+    Video,
+}
+
+impl MediaType {
+    pub const VARIANTS: &'static [&'static str] = &["all", "photo", "video"];
+}
+
+impl FromStr for MediaType {
+    type Err = String;
+
+    fn from_str(media_type: &str) -> std::result::Result<MediaType, String> {
+        match media_type {
+            "all" => Ok(MediaType::All),
+            "photo" => Ok(MediaType::Photo),
+            "video" => Ok(MediaType::Video),
+            _ => Err(format!("unknown media type {}", media_type)),
+        }
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let media_type = match self {
+            MediaType::All => "all",
+            MediaType::Photo => "photo",
+            MediaType::Video => "video",
+        };
+        write!(f, "{}", media_type)
+    }
+}
+
+/// Narrows the media a search of the library returns, on top of the dates.
+/// The defaults return everything that isn't archived, as a search with only
+/// a date filter does.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilters {
+    /// only media in at least one of these categories
+    pub include_categories: Vec<ContentCategory>,
+    /// no media in any of these categories
+    pub exclude_categories: Vec<ContentCategory>,
+    pub media_type: MediaType,
+    /// only media marked as favorites
+    pub favorites: bool,
+    /// also media that have been archived
+    pub include_archived: bool,
+}
+
+impl SearchFilters {
+    /// Whether these filters leave out media a search with only a date filter
+    /// would return. Including archived media only adds to it.
+    pub fn narrows(&self) -> bool {
+        !self.include_categories.is_empty()
+            || !self.exclude_categories.is_empty()
+            || self.media_type != MediaType::All
+            || self.favorites
+    }
+
+    /// Adds these filters to the `filters` object of a search request.
+    pub(crate) fn apply(&self, filters: &mut Value) {
+        if !self.include_categories.is_empty() || !self.exclude_categories.is_empty() {
+            let names = |categories: &[ContentCategory]| -> Vec<String> {
+                categories.iter().map(ContentCategory::api_name).collect()
+            };
+            let mut content_filter = json!({});
+            if !self.include_categories.is_empty() {
+                content_filter["includedContentCategories"] =
+                    json!(names(&self.include_categories));
+            }
+            if !self.exclude_categories.is_empty() {
+                content_filter["excludedContentCategories"] =
+                    json!(names(&self.exclude_categories));
+            }
+            filters["contentFilter"] = content_filter;
+        }
+        let media_type = match self.media_type {
+            MediaType::All => None,
+            MediaType::Photo => Some("PHOTO"),
+            MediaType::Video => Some("VIDEO"),
+        };
+        if let Some(media_type) = media_type {
+            filters["mediaTypeFilter"] = json!({ "mediaTypes": [media_type] });
+        }
+        if self.favorites {
+            filters["featureFilter"] = json!({ "includedFeatures": ["FAVORITES"] });
+        }
+        if self.include_archived {
+            filters["includeArchivedMedia"] = json!(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+
+    use super::{ContentCategory, MediaType, SearchFilters};
+
+    #[test]
+    fn test_parse_filters() {
+        for variant in ContentCategory::VARIANTS {
+            let category: ContentCategory = variant.parse().unwrap();
+            assert_eq!(*variant, category.to_string());
+        }
+        assert_eq!(
+            Ok(ContentCategory("screenshots")),
+            "SCREENSHOTS".parse::<ContentCategory>()
+        );
+        assert!("memes".parse::<ContentCategory>().is_err());
+
+        for variant in MediaType::VARIANTS {
+            let media_type: MediaType = variant.parse().unwrap();
+            assert_eq!(*variant, media_type.to_string());
+        }
+        assert!("gif".parse::<MediaType>().is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut filters = json!({ "dateFilter": {} });
+        SearchFilters::default().apply(&mut filters);
+        assert_eq!(json!({ "dateFilter": {} }), filters);
+
+        let search_filters = SearchFilters {
+            exclude_categories: vec!["screenshots".parse().unwrap(), "receipts".parse().unwrap()],
+            media_type: MediaType::Photo,
+            favorites: true,
+            include_archived: true,
+            ..Default::default()
+        };
+        search_filters.apply(&mut filters);
+        assert_eq!(
+            json!({
+                "dateFilter": {},
+                "contentFilter": {
+                    "excludedContentCategories": ["SCREENSHOTS", "RECEIPTS"]
+                },
+                "mediaTypeFilter": { "mediaTypes": ["PHOTO"] },
+                "featureFilter": { "includedFeatures": ["FAVORITES"] },
+                "includeArchivedMedia": true
+            }),
+            filters
+        );
+    }
+}
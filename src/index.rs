@@ -0,0 +1,476 @@
+use chrono::{Datelike, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{checksum_file, parse_creation_time, Error, Result, TimeZone, YearMonthDay};
+
+pub(crate) const INDEX_DIR: &str = ".litho";
+const INDEX_FILE: &str = "index.jsonl";
+
+/// The on-disk record of every media item litho has seen, keyed by the
+/// Google media id.
+///
+/// The index lives at `<album_dir>/.litho/index.jsonl` and is append only:
+/// each line is a JSON encoded `IndexEntry` and when an id appears more than
+/// once the last line wins.
+pub struct SyncIndex {
+    path: PathBuf,
+    entries: HashMap<String, IndexEntry>,
+    /// the id of the media last recorded at each path
+    paths: HashMap<PathBuf, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexEntry {
+    pub id: String,
+    pub filename: String,
+    /// location of the file relative to the album directory
+    pub path: PathBuf,
+    pub size: u64,
+    /// hex encoded SHA-256 of the file contents
+    pub checksum: String,
+    pub creation_time: String,
+    pub status: DownloadStatus,
+    /// the album the media was downloaded from, when it wasn't found by date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_id: Option<String>,
+    /// who added the media to a shared album, when it wasn't the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contributor: Option<String>,
+    /// whether the media was found by a search narrowed by filters such as
+    /// `--favorites`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub filtered: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DownloadStatus {
+    Downloaded,
+    Failed,
+    /// a video Google had not finished processing when it was seen
+    Processing,
+    /// overwritten by another item at the same path, under the overwrite
+    /// collision policy, and so not downloaded again
+    Replaced,
+}
+
+/// A downloaded file that no longer matches what the index recorded for it.
+#[derive(Debug, PartialEq)]
+pub struct VerifyFailure {
+    pub entry: IndexEntry,
+    pub problem: Problem,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    Missing,
+    SizeMismatch { actual: u64 },
+    ChecksumMismatch { actual: String },
+}
+
+impl SyncIndex {
+    pub fn open(album_dir: &Path) -> Result<SyncIndex> {
+        let mut dir = PathBuf::from(album_dir);
+        dir.push(INDEX_DIR);
+        fs::create_dir_all(&dir).map_err(|err| Error::io(&dir, err))?;
+        let path = dir.join(INDEX_FILE);
+
+        let mut index = SyncIndex {
+            path: path.clone(),
+            entries: HashMap::new(),
+            paths: HashMap::new(),
+        };
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(index),
+            Err(err) => return Err(Error::io(&path, err)),
+        };
+        let mut offset = 0;
+        for line in contents.split_inclusive(|byte| *byte == b'\n') {
+            let start = offset;
+            offset += line.len();
+            let text = String::from_utf8_lossy(line);
+            if text.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<IndexEntry>(&text) {
+                Ok(entry) => index.insert(entry),
+                // a run that was cut off part way through `record` leaves a
+                // torn last line, which is dropped so the next record starts
+                // on a line of its own
+                Err(err) if offset == contents.len() => {
+                    println!(
+                        "warning: dropping the incomplete last record of {}: {}",
+                        path.display(),
+                        err
+                    );
+                    index.truncate(start as u64)?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(index)
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .and_then(|file| file.set_len(len))
+            .map_err(|err| Error::io(&self.path, err))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&IndexEntry> {
+        self.entries.get(id)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &IndexEntry> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn record(&mut self, entry: IndexEntry) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| Error::io(&self.path, err))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+            .map_err(|err| Error::io(&self.path, err))?;
+        self.insert(entry);
+        Ok(())
+    }
+
+    /// The entry for the media stored at `path`, relative to the album
+    /// directory.
+    pub fn owner(&self, path: &Path) -> Option<&IndexEntry> {
+        self.paths.get(path).and_then(|id| self.entries.get(id))
+    }
+
+    fn insert(&mut self, entry: IndexEntry) {
+        if let Some(previous) = self.entries.get(&entry.id) {
+            if self.paths.get(&previous.path) == Some(&entry.id) {
+                self.paths.remove(&previous.path);
+            }
+        }
+        self.paths.insert(entry.path.clone(), entry.id.clone());
+        self.entries.insert(entry.id.clone(), entry);
+    }
+
+    /// Checks every downloaded entry against the file under `album_dir`,
+    /// returning the ones that are missing or whose contents have changed.
+    pub fn verify(&self, album_dir: &Path) -> Result<Vec<VerifyFailure>> {
+        let mut failures = Vec::new();
+        let downloaded = self
+            .entries
+            .values()
+            .filter(|entry| entry.status == DownloadStatus::Downloaded);
+        for entry in downloaded {
+            let path = album_dir.join(&entry.path);
+            let problem = if !path.exists() {
+                Some(Problem::Missing)
+            } else {
+                let (size, actual) = checksum_file(&path)?;
+                if size != entry.size {
+                    Some(Problem::SizeMismatch { actual: size })
+                } else if actual != entry.checksum {
+                    Some(Problem::ChecksumMismatch { actual })
+                } else {
+                    None
+                }
+            };
+            if let Some(problem) = problem {
+                failures.push(VerifyFailure {
+                    entry: entry.clone(),
+                    problem,
+                });
+            }
+        }
+        failures.sort_by(|a, b| a.entry.path.cmp(&b.entry.path));
+        Ok(failures)
+    }
+
+    /// The creation date of the newest item that has been downloaded, which is
+    /// where a sync should resume from. Videos that were still processing pull
+    /// it back so that they are picked up once they are ready. The date is the
+    /// one in `timezone`, matching the directories media are filed under.
+    ///
+    /// Media downloaded from an album are left out, since they say nothing
+    /// about how far through the library a sync has got. Neither do media
+    /// downloaded by a filtered search, unless `filtered` says the sync that
+    /// resumes is filtered as well.
+    pub fn most_recent_date(&self, timezone: TimeZone, filtered: bool) -> Option<YearMonthDay> {
+        let newest = self.created_on(DownloadStatus::Downloaded, filtered).max();
+        let processing = self.created_on(DownloadStatus::Processing, filtered).min();
+        let resume_from = match (newest, processing) {
+            (Some(newest), Some(processing)) => Some(newest.min(processing)),
+            (newest, processing) => newest.or(processing),
+        };
+        resume_from.map(|created_on| {
+            let created_on = timezone.from_utc(created_on);
+            YearMonthDay {
+                year: created_on.year(),
+                month: created_on.month(),
+                day: created_on.day(),
+            }
+        })
+    }
+
+    fn created_on(
+        &self,
+        status: DownloadStatus,
+        filtered: bool,
+    ) -> impl Iterator<Item = NaiveDateTime> + '_ {
+        self.entries
+            .values()
+            .filter(move |entry| {
+                entry.status == status && entry.album_id.is_none() && (filtered || !entry.filtered)
+            })
+            .filter_map(|entry| parse_creation_time(&entry.creation_time).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    use crate::{TimeZone, YearMonthDay};
+
+    use super::{DownloadStatus, IndexEntry, Problem, SyncIndex};
+
+    #[test]
+    fn test_index_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let mut index = SyncIndex::open(temp_dir.path()).unwrap();
+        assert!(index.is_empty());
+        index
+            .record(entry(
+                "abc123",
+                "2014-10-02T15:01:23Z",
+                DownloadStatus::Failed,
+            ))
+            .unwrap();
+        index
+            .record(entry(
+                "abc123",
+                "2014-10-02T15:01:23Z",
+                DownloadStatus::Downloaded,
+            ))
+            .unwrap();
+        index
+            .record(entry(
+                "def456",
+                "2014-10-03T15:01:23Z",
+                DownloadStatus::Downloaded,
+            ))
+            .unwrap();
+
+        let reopened = SyncIndex::open(temp_dir.path()).unwrap();
+        assert_eq!(2, reopened.len());
+        assert_eq!(
+            DownloadStatus::Downloaded,
+            reopened.get("abc123").unwrap().status
+        );
+        assert_eq!(None, reopened.get("xyz789"));
+        // both were recorded at the same path, the last one owns it
+        let owner = reopened.owner(Path::new("2014/10/02/test.jpg")).unwrap();
+        assert_eq!("def456", owner.id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_torn_record() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let mut index = SyncIndex::open(temp_dir.path()).unwrap();
+        index
+            .record(entry(
+                "abc123",
+                "2014-10-02T15:01:23Z",
+                DownloadStatus::Downloaded,
+            ))
+            .unwrap();
+        // as if the power went out while the next record was being written
+        let path = temp_dir.path().join(".litho").join("index.jsonl");
+        let mut contents = fs::read_to_string(&path)?;
+        contents.push_str(r#"{"id":"def456","path":"2014/10/0"#);
+        fs::write(&path, contents)?;
+
+        let mut reopened = SyncIndex::open(temp_dir.path()).unwrap();
+        assert_eq!(1, reopened.len());
+        assert!(reopened.get("abc123").is_some());
+        reopened
+            .record(entry(
+                "def456",
+                "2014-10-03T15:01:23Z",
+                DownloadStatus::Downloaded,
+            ))
+            .unwrap();
+
+        let reopened = SyncIndex::open(temp_dir.path()).unwrap();
+        assert_eq!(2, reopened.len());
+        assert!(reopened.get("def456").is_some());
+
+        // only the last line can have been torn
+        let mut contents = fs::read_to_string(&path)?;
+        contents.insert_str(0, "{not json\n");
+        fs::write(&path, contents)?;
+        assert!(SyncIndex::open(temp_dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_most_recent_date() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let mut index = SyncIndex::open(temp_dir.path()).unwrap();
+        assert_eq!(None, index.most_recent_date(TimeZone::Utc, false));
+        index
+            .record(entry(
+                "abc123",
+                "2014-10-02T15:01:23.045Z",
+                DownloadStatus::Downloaded,
+            ))
+            .unwrap();
+        index
+            .record(entry(
+                "def456",
+                "2015-01-01T15:01:23Z",
+                DownloadStatus::Failed,
+            ))
+            .unwrap();
+        let expected = YearMonthDay {
+            year: 2014,
+            month: 10,
+            day: 2,
+        };
+        assert_eq!(Some(expected), index.most_recent_date(TimeZone::Utc, false));
+        index
+            .record(entry(
+                "xyz789",
+                "2013-05-06T15:01:23Z",
+                DownloadStatus::Processing,
+            ))
+            .unwrap();
+        let expected = YearMonthDay {
+            year: 2013,
+            month: 5,
+            day: 6,
+        };
+        assert_eq!(Some(expected), index.most_recent_date(TimeZone::Utc, false));
+        // early in the morning UTC is still the day before in New York
+        let expected = YearMonthDay {
+            year: 2013,
+            month: 5,
+            day: 5,
+        };
+        index
+            .record(entry(
+                "xyz789",
+                "2013-05-06T03:01:23Z",
+                DownloadStatus::Processing,
+            ))
+            .unwrap();
+        let new_york = "America/New_York".parse().unwrap();
+        assert_eq!(Some(expected), index.most_recent_date(new_york, false));
+
+        let mut from_album = entry("uvw456", "2020-01-01T15:01:23Z", DownloadStatus::Downloaded);
+        from_album.album_id = Some(String::from("album1"));
+        index.record(from_album).unwrap();
+        assert_eq!(Some(expected), index.most_recent_date(new_york, false));
+
+        // filtered syncs resume from what they downloaded, others don't
+        let temp_dir = tempdir()?;
+        let mut index = SyncIndex::open(temp_dir.path()).unwrap();
+        index
+            .record(entry(
+                "abc123",
+                "2014-10-02T15:01:23Z",
+                DownloadStatus::Downloaded,
+            ))
+            .unwrap();
+        let mut filtered = entry("rst321", "2020-01-01T15:01:23Z", DownloadStatus::Downloaded);
+        filtered.filtered = true;
+        filtered.path = PathBuf::from("2020/01/01/test.jpg");
+        index.record(filtered).unwrap();
+        let unfiltered = YearMonthDay {
+            year: 2014,
+            month: 10,
+            day: 2,
+        };
+        assert_eq!(
+            Some(unfiltered),
+            index.most_recent_date(TimeZone::Utc, false)
+        );
+        let filtered = YearMonthDay {
+            year: 2020,
+            month: 1,
+            day: 1,
+        };
+        assert_eq!(Some(filtered), index.most_recent_date(TimeZone::Utc, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_verify() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let mut index = SyncIndex::open(temp_dir.path()).unwrap();
+        let mut intact = entry("abc123", "2014-10-02T15:01:23Z", DownloadStatus::Downloaded);
+        intact.checksum =
+            String::from("65ab12a8ff3263fbc257e5ddf0aa563c64573d0bab1f1115b9b107834cfa6971");
+        let mut changed = intact.clone();
+        changed.id = String::from("def456");
+        changed.path = PathBuf::from("2014/10/02/changed.jpg");
+        let mut missing = intact.clone();
+        missing.id = String::from("xyz789");
+        missing.path = PathBuf::from("2014/10/02/missing.jpg");
+        index.record(intact).unwrap();
+        index.record(changed).unwrap();
+        index.record(missing).unwrap();
+
+        let day = temp_dir.path().join("2014/10/02");
+        fs::create_dir_all(&day)?;
+        fs::write(day.join("test.jpg"), b"\xca\xfe\xba\xbe")?;
+        fs::write(day.join("changed.jpg"), b"\xde\xad\xbe\xef")?;
+
+        let failures = index.verify(temp_dir.path()).unwrap();
+        assert_eq!(2, failures.len());
+        assert_eq!("def456", failures[0].entry.id);
+        assert!(matches!(
+            failures[0].problem,
+            Problem::ChecksumMismatch { .. }
+        ));
+        assert_eq!("xyz789", failures[1].entry.id);
+        assert_eq!(Problem::Missing, failures[1].problem);
+        Ok(())
+    }
+
+    fn entry(id: &str, creation_time: &str, status: DownloadStatus) -> IndexEntry {
+        IndexEntry {
+            id: String::from(id),
+            filename: String::from("test.jpg"),
+            path: PathBuf::from("2014/10/02/test.jpg"),
+            size: 4,
+            checksum: String::from("cafebabe"),
+            creation_time: String::from(creation_time),
+            status,
+            album_id: None,
+            contributor: None,
+            filtered: false,
+        }
+    }
+}
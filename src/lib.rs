@@ -1,40 +1,169 @@
 use base64::encode_config;
-use chrono::{Datelike, NaiveDateTime};
+use chrono::{DateTime, NaiveDateTime};
 use rand::Rng;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sha2::{Digest, Sha256};
-use tiny_http;
-use urlencoding::encode;
 
+use reqwest::blocking::Response;
 use reqwest::StatusCode;
-use std::convert::TryInto;
-use std::fs::{self, create_dir_all, File};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, create_dir_all, File, FileTimes, OpenOptions};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::mpsc::{self, Receiver, SendError, Sender};
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use std::vec::Vec;
 
+mod collision;
+mod exif;
+mod filter;
+mod index;
+mod mirror;
+mod names;
+mod profile;
+mod range;
+mod retry;
+mod template;
+mod timezone;
+mod token;
+mod xmp;
+
+pub use collision::CollisionPolicy;
+pub use filter::{ContentCategory, MediaType, SearchFilters};
+use index::INDEX_DIR;
+pub use index::{DownloadStatus, IndexEntry, Problem, SyncIndex, VerifyFailure};
+pub use mirror::{AlbumMirror, LinkKind, MirrorChanges, MIRROR_DIR};
+pub use names::{migrate_names, sanitize_filename};
+pub use profile::{Profile, Profiles};
+pub use range::{parse_date, DateRange, MAX_RANGES};
+pub use retry::RetryPolicy;
+pub use template::{PathTemplate, ALBUM_TEMPLATE, DEFAULT_TEMPLATE, UNKNOWN_DATE_DIR};
+pub use timezone::TimeZone;
+pub use token::{AccessToken, RefreshingTokenProvider, TokenProvider};
+
 const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
     abcdefghijklmnopqrstuvwxyz\
     0123456789-.~_";
 
 const PAGE_SIZE: u32 = 25;
+/// The most albums the API returns in one page.
+const ALBUM_PAGE_SIZE: u32 = 50;
 
 const PAUSE_FETCH: Duration = Duration::from_secs(1);
 const PAUSE_WRITE: Duration = Duration::from_millis(250);
 
+const PARTIAL_SUFFIX: &str = ".litho-part";
+
+/// Google access tokens last an hour when the response doesn't say otherwise.
+const DEFAULT_EXPIRES_IN: u64 = 60 * 60;
+
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum Error {
-    SerError,
-    FetchError,
-    IOError,
-    SendError,
+    /// A response or index record could not be (de)serialized.
+    Serialization(serde_json::Error),
+    /// A request could not be sent or its body could not be read.
+    Request(reqwest::Error),
+    /// The server answered with a status other than success.
+    Status { status: StatusCode, body: String },
+    /// A file system operation on `path` failed.
+    Io { path: PathBuf, source: io::Error },
+    /// A token response did not contain the expected field.
+    MissingField(String),
+    /// The local server for the authorization redirect could not be started.
+    Listen(Box<dyn std::error::Error + Send + Sync>),
+    /// The authorization flow ended without receiving a code.
+    Authorization,
+    /// A creation time could not be parsed.
+    CreationTime {
+        value: String,
+        source: chrono::ParseError,
+    },
+    /// The connection failed while the body of a download was being read.
+    Interrupted { path: PathBuf, source: io::Error },
+    /// The download ended before the whole body arrived.
+    Incomplete {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    /// The receiving end of a channel hung up.
+    Send,
+    /// A path template could not be parsed.
+    Template { template: String, reason: String },
+    /// A date or range of dates given to search by could not be parsed.
+    Date { date: String, reason: String },
+    /// A profile could not be added.
+    Profile { name: String, reason: String },
+}
+
+impl Error {
+    fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            path: PathBuf::from(path),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Serialization(err) => write!(f, "unable to (de)serialize: {}", err),
+            Error::Request(err) => write!(f, "request failed: {}", err),
+            Error::Status { status, body } => {
+                write!(f, "unexpected response status {}: {}", status, body)
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::MissingField(field) => write!(f, "response did not contain {}", field),
+            Error::Listen(err) => write!(f, "unable to listen for authorization: {}", err),
+            Error::Authorization => write!(f, "authorization did not complete"),
+            Error::CreationTime { value, source } => {
+                write!(f, "invalid creation time {}: {}", value, source)
+            }
+            Error::Interrupted { path, source } => {
+                write!(f, "{}: download interrupted: {}", path.display(), source)
+            }
+            Error::Incomplete {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: expected {} bytes but received {}",
+                path.display(),
+                expected,
+                actual
+            ),
+            Error::Send => write!(f, "receiver hung up"),
+            Error::Template { template, reason } => {
+                write!(f, "invalid path template {}: {}", template, reason)
+            }
+            Error::Date { date, reason } => write!(f, "invalid date {}: {}", date, reason),
+            Error::Profile { name, reason } => write!(f, "invalid profile {}: {}", name, reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Serialization(err) => Some(err),
+            Error::Request(err) => Some(err),
+            Error::Io { source, .. } => Some(source),
+            Error::Interrupted { source, .. } => Some(source),
+            Error::Listen(err) => Some(err.as_ref()),
+            Error::CreationTime { source, .. } => Some(source),
+            _ => None,
+        }
+    }
 }
 
 pub struct TokenFetcher<'a> {
@@ -46,43 +175,197 @@ pub struct TokenFetcher<'a> {
     refresh_uri: &'a str,
 }
 
-pub struct MediaFetcher {
+pub struct MediaFetcher<'a> {
     base_uri: String,
-    access_token: String,
-    start_filter: YearMonthDay,
-    end_filter: YearMonthDay,
+    tokens: Box<dyn TokenProvider + 'a>,
+    date_ranges: Vec<DateRange>,
+    retry_policy: RetryPolicy,
+    album_id: Option<String>,
+    filters: SearchFilters,
 }
 
 pub struct MediaWriter<'a> {
     album_dir: &'a PathBuf,
+    retry_policy: RetryPolicy,
+    jobs: usize,
+    json_sidecar: bool,
+    xmp_sidecar: bool,
+    embed_exif: bool,
+    path_template: PathTemplate,
+    album: Option<AlbumInfo>,
+    filtered: bool,
+    collision_policy: CollisionPolicy,
+    timezone: TimeZone,
+    index: Mutex<SyncIndex>,
+    /// paths handed out to downloads in this run, by media id, so that
+    /// concurrent downloads never pick the same one
+    claimed: Mutex<HashMap<PathBuf, String>>,
+    /// the media handed out to downloads so far, which is what limits count
+    taken: AtomicU32,
 }
 
+/// A page of the media items returned by a search.
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct Album {
+pub struct MediaPage {
     #[serde(default = "Vec::new")]
     pub media_items: Vec<Media>,
     #[serde(default)]
     pub next_page_token: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The name `MediaPage` had before albums themselves could be listed.
+#[deprecated(note = "renamed to MediaPage, albums are described by AlbumInfo")]
+pub type Album = MediaPage;
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumInfo {
+    pub id: String,
+    /// albums can be created without a title
+    #[serde(default)]
+    pub title: String,
+    pub product_url: Option<String>,
+    #[serde(default, with = "int64")]
+    pub media_items_count: Option<u64>,
+    pub cover_photo_base_url: Option<String>,
+    /// only present for albums that are shared
+    pub share_info: Option<ShareInfo>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareInfo {
+    pub shareable_url: Option<String>,
+    #[serde(default)]
+    pub is_joined: bool,
+    #[serde(default)]
+    pub is_owned: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AlbumPage {
+    #[serde(default = "Vec::new", alias = "sharedAlbums")]
+    albums: Vec<AlbumInfo>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Media {
     pub id: String,
+    pub description: Option<String>,
+    pub product_url: Option<String>,
     pub base_url: String,
     pub mime_type: String,
     pub media_metadata: MediaMetadata,
+    pub contributor_info: Option<ContributorInfo>,
     pub filename: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Media {
+    /// Videos are downloaded with `=dv`, since `=d` only returns a still frame.
+    pub fn is_video(&self) -> bool {
+        self.mime_type.starts_with("video/") || self.media_metadata.video.is_some()
+    }
+
+    /// How far Google has got with processing the media, which has to be
+    /// ready before it can be downloaded. Only videos are ever processed.
+    pub fn processing_status(&self) -> VideoProcessingStatus {
+        match &self.media_metadata.video {
+            Some(Video {
+                status: Some(status),
+                ..
+            }) => *status,
+            _ => VideoProcessingStatus::Ready,
+        }
+    }
+
+    /// When the media was created, as recorded by Google in UTC.
+    pub fn created_on(&self) -> Result<NaiveDateTime> {
+        parse_creation_time(&self.media_metadata.creation_time)
+    }
+
+    /// The URL the media bytes are fetched from. Photos ask for their original
+    /// dimensions when they are known, otherwise Google may scale them down.
+    pub fn download_url(&self) -> String {
+        let metadata = &self.media_metadata;
+        if self.is_video() {
+            format!("{}=dv", self.base_url)
+        } else if let (Some(width), Some(height)) = (metadata.width, metadata.height) {
+            format!("{}=w{}-h{}-d", self.base_url, width, height)
+        } else {
+            format!("{}=d", self.base_url)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct MediaMetadata {
     pub creation_time: String,
+    #[serde(default, with = "int64")]
+    pub width: Option<u64>,
+    #[serde(default, with = "int64")]
+    pub height: Option<u64>,
+    pub photo: Option<Photo>,
+    pub video: Option<Video>,
+}
+
+impl MediaMetadata {
+    /// The make and model of the camera from the photo or video details,
+    /// whichever Google returned.
+    pub fn camera(&self) -> (Option<&str>, Option<&str>) {
+        match (&self.photo, &self.video) {
+            (Some(photo), _) => (photo.camera_make.as_deref(), photo.camera_model.as_deref()),
+            (None, Some(video)) => (video.camera_make.as_deref(), video.camera_model.as_deref()),
+            (None, None) => (None, None),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Photo {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub focal_length: Option<f64>,
+    pub aperture_f_number: Option<f64>,
+    pub iso_equivalent: Option<u32>,
+    /// a duration in seconds with an `s` suffix, e.g. `0.008s`
+    pub exposure_time: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Video {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub fps: Option<f64>,
+    pub status: Option<VideoProcessingStatus>,
+}
+
+/// Who added the media, for items in a shared album.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ContributorInfo {
+    pub profile_picture_base_url: Option<String>,
+    pub display_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum VideoProcessingStatus {
+    Processing,
+    Ready,
+    Failed,
+    #[serde(other)]
+    Unspecified,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct YearMonthDay {
     pub year: i32,
@@ -90,22 +373,51 @@ pub struct YearMonthDay {
     pub day: u32,
 }
 
+/// The Photos Library API encodes int64 values as JSON strings.
+mod int64 {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Int64 {
+        String(String),
+        Number(u64),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&value.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        match Option::<Int64>::deserialize(deserializer)? {
+            Some(Int64::String(value)) => value.parse().map(Some).map_err(D::Error::custom),
+            Some(Int64::Number(value)) => Ok(Some(value)),
+            None => Ok(None),
+        }
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Error {
-        // XXX: need to map the errors so that the underlying failure message
-        // can be used
-        print!("Err {}", err);
-        Error::SerError
+        Error::Serialization(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        Error::Request(err)
     }
 }
 
-// XXX: this copy pasta could probably be written differently
 impl From<SendError<Vec<Media>>> for Error {
-    fn from(err: SendError<Vec<Media>>) -> Error {
-        // XXX: need to map the errors so that the underlying failure message
-        // can be used
-        print!("Err {}", err);
-        Error::SendError
+    fn from(_: SendError<Vec<Media>>) -> Error {
+        Error::Send
     }
 }
 
@@ -127,11 +439,11 @@ impl<'a> TokenFetcher<'a> {
             .collect();
         let code_challenge = TokenFetcher::gen_code_challenge(&code_verifier);
         let redirect_uri = format!("http://{}:{}", TokenFetcher::HOST, TokenFetcher::PORT);
-        let auth_uri = TokenFetcher::build_auth_url(&client_id, &code_challenge, &redirect_uri);
+        let auth_uri = TokenFetcher::build_auth_url(client_id, &code_challenge, &redirect_uri);
         TokenFetcher {
             client_id,
             client_secret,
-            code_verifier: String::from_utf8(code_verifier).unwrap(),
+            code_verifier: code_verifier.iter().map(|&c| c as char).collect(),
             auth_uri,
             redirect_uri,
             refresh_uri,
@@ -146,11 +458,13 @@ impl<'a> TokenFetcher<'a> {
     }
 
     fn build_auth_url(client_id: &str, code_challenge: &str, redirect_uri: &str) -> reqwest::Url {
-        let mut url = reqwest::Url::parse("https://accounts.google.com/o/oauth2/v2/auth").unwrap();
+        let mut url = reqwest::Url::parse("https://accounts.google.com/o/oauth2/v2/auth")
+            .expect("the authorization url is valid");
         url.query_pairs_mut()
             .append_pair("client_id", client_id)
             .append_pair("redirect_uri", redirect_uri)
             .append_pair("response_type", "code")
+            // read only access covers the albums shared with the user too
             .append_pair(
                 "scope",
                 "https://www.googleapis.com/auth/photoslibrary.readonly",
@@ -160,114 +474,186 @@ impl<'a> TokenFetcher<'a> {
         url
     }
 
-    fn start(&self, tx: Sender<String>) {
+    fn start(&self, tx: Sender<String>) -> Result<()> {
         let server =
             tiny_http::Server::http(format!("{}:{}", TokenFetcher::HOST, TokenFetcher::PORT))
-                .unwrap();
+                .map_err(Error::Listen)?;
 
-        let m = Mutex::new(Some(tx.clone()));
         thread::spawn(move || {
-            for request in server.recv() {
+            if let Ok(request) = server.recv() {
                 println!("Request received. {} {}", request.method(), request.url());
                 let code = extract_code(request.url());
-                let result: Result<&str> = match code {
-                    Some(x) => {
-                        if let Some(tx) = m.lock().unwrap().take() {
-                            tx.send(x).unwrap();
-                        }
-                        Ok("Authorization complete.")
-                    }
-                    None => Ok("Authorization failed."),
+                // if nobody is waiting on the code any more there is nothing to complete
+                let result = match code.map(|x| tx.send(x)) {
+                    Some(Ok(_)) => "Authorization complete.",
+                    _ => "Authorization failed.",
                 };
-                let response = tiny_http::Response::from_string(result.unwrap());
+                let response = tiny_http::Response::from_string(result);
                 let _ = request.respond(response);
             }
         });
+        Ok(())
     }
 
     fn stop(&self) {}
 
     pub fn fetch_refresh(&self) -> Result<String> {
         let (tx, rx) = mpsc::channel();
-        self.start(tx);
+        self.start(tx)?;
         println!(
             "Open your browser and authorize access:\n  {}",
             self.auth_uri.as_str()
         );
-        let code = rx.recv().unwrap();
+        let code = rx.recv().map_err(|_| Error::Authorization)?;
         // println!("Code was: {}", code);
         self.stop();
-        self.refresh(
-            [
-                ("client_id", self.client_id),
-                ("client_secret", self.client_secret),
-                ("code", &code),
-                ("code_verifier", &self.code_verifier),
-                ("grant_type", "authorization_code"),
-                ("redirect_uri", &self.redirect_uri),
-            ],
-            "refresh_token",
-        )
+        let response = self.refresh([
+            ("client_id", self.client_id),
+            ("client_secret", self.client_secret),
+            ("code", &code),
+            ("code_verifier", &self.code_verifier),
+            ("grant_type", "authorization_code"),
+            ("redirect_uri", &self.redirect_uri),
+        ])?;
+        string_field(&response, "refresh_token")
     }
 
     pub fn fetch_access(&self, refresh_token: &str) -> Result<String> {
-        self.refresh(
-            [
-                ("client_id", &self.client_id),
-                ("client_secret", &self.client_secret),
-                ("code", ""),
-                ("code_verifier", ""),
-                ("grant_type", "refresh_token"),
-                ("refresh_token", &refresh_token),
-            ],
-            "access_token",
-        )
-    }
-
-    fn refresh(&self, params: [(&str, &str); 6], field: &str) -> Result<String> {
+        Ok(self.fetch_access_token(refresh_token)?.token)
+    }
+
+    /// Exchanges the refresh token for an access token, keeping track of
+    /// when it expires.
+    pub fn fetch_access_token(&self, refresh_token: &str) -> Result<AccessToken> {
+        let fetched_at = Instant::now();
+        let response = self.refresh([
+            ("client_id", self.client_id),
+            ("client_secret", self.client_secret),
+            ("code", ""),
+            ("code_verifier", ""),
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ])?;
+        let token = string_field(&response, "access_token")?;
+        let expires_in = response["expires_in"]
+            .as_u64()
+            .unwrap_or(DEFAULT_EXPIRES_IN);
+        Ok(AccessToken {
+            token,
+            expires_at: fetched_at + Duration::from_secs(expires_in),
+        })
+    }
+
+    fn refresh(&self, params: [(&str, &str); 6]) -> Result<serde_json::Value> {
         // println!("params={:?}", params);
         let client = reqwest::blocking::Client::new();
         let request = client.post(self.refresh_uri).form(&params);
-        let result = request.send();
-        match result {
-            Ok(r) => {
-                let refresh_value: serde_json::Value =
-                    serde_json::from_str(&r.text().unwrap()).unwrap();
-                // println!("field={}", field);
-                // println!("text={}", refresh_value);
-                let value = refresh_value[field].as_str().unwrap();
-                // println!("refresh_value={}", value);
-                Ok(String::from(value))
-            }
-            Err(_) => Err(Error::FetchError),
+        let response = request.send()?;
+        let status = response.status();
+        let body = response.text()?;
+        if !status.is_success() {
+            return Err(Error::Status { status, body });
         }
+        let refresh_value: serde_json::Value = serde_json::from_str(&body)?;
+        // println!("text={}", refresh_value);
+        Ok(refresh_value)
+    }
+}
+
+fn string_field(value: &serde_json::Value, field: &str) -> Result<String> {
+    match value[field].as_str() {
+        Some(value) => Ok(String::from(value)),
+        None => Err(Error::MissingField(String::from(field))),
     }
 }
 
 fn extract_code(url: &str) -> Option<String> {
-    let re = Regex::new(r"code=(.*?)(&|$)").unwrap();
-    let captures = re.captures(url).unwrap();
-    Some(String::from(captures.get(1).unwrap().as_str()))
+    let re = Regex::new(r"code=(.*?)(&|$)").expect("the code pattern is valid");
+    let captures = re.captures(url)?;
+    Some(String::from(captures.get(1)?.as_str()))
 }
 
-impl MediaFetcher {
-    pub fn new(
+impl<'a> MediaFetcher<'a> {
+    /// A fetcher that searches the library for media created in any of
+    /// `date_ranges`, or the whole library, whatever the dates, when there are
+    /// none.
+    pub fn new<T: TokenProvider + 'a>(
         base_uri: String,
-        access_token: String,
-        start_filter: YearMonthDay,
-        end_filter: YearMonthDay,
-    ) -> MediaFetcher {
+        tokens: T,
+        date_ranges: Vec<DateRange>,
+    ) -> MediaFetcher<'a> {
         MediaFetcher {
             base_uri,
-            access_token,
-            start_filter,
-            end_filter,
+            tokens: Box::new(tokens),
+            date_ranges,
+            retry_policy: RetryPolicy::default(),
+            album_id: None,
+            filters: SearchFilters::default(),
         }
     }
 
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> MediaFetcher<'a> {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Fetches the media in the album with `album_id`, in the order they are
+    /// in the album, instead of searching the library by date.
+    pub fn with_album(mut self, album_id: &str) -> MediaFetcher<'a> {
+        self.album_id = Some(String::from(album_id));
+        self
+    }
+
+    /// Narrows a search of the library by content, media type and the like.
+    /// Albums can't be filtered, so the filters are ignored with `with_album`.
+    pub fn with_filters(mut self, filters: SearchFilters) -> MediaFetcher<'a> {
+        self.filters = filters;
+        self
+    }
+
+    /// Lists the albums in the library, following every page.
+    pub fn fetch_albums(&self) -> Result<Vec<AlbumInfo>> {
+        self.fetch_album_list("albums")
+    }
+
+    /// Lists the albums others have shared with the user, along with those the
+    /// user has shared, following every page. Their media can be fetched with
+    /// `with_album` like any other album.
+    pub fn fetch_shared_albums(&self) -> Result<Vec<AlbumInfo>> {
+        self.fetch_album_list("sharedAlbums")
+    }
+
+    fn fetch_album_list(&self, collection: &str) -> Result<Vec<AlbumInfo>> {
+        let client = reqwest::blocking::Client::new();
+        let uri = format!("{}/v1/{}", self.base_uri, collection);
+        let mut albums = Vec::new();
+        let mut next_page_token: Option<String> = None;
+        loop {
+            let mut query = vec![("pageSize", ALBUM_PAGE_SIZE.to_string())];
+            if let Some(token) = next_page_token {
+                query.push(("pageToken", token));
+            }
+            let body = self.send(|bearer_token| {
+                client
+                    .get(&uri)
+                    .header("Authorization", bearer_token)
+                    .query(&query)
+                    .send()
+            })?;
+            let page: AlbumPage = serde_json::from_str(&body)?;
+            albums.extend(page.albums);
+            next_page_token = page.next_page_token.filter(|token| !token.is_empty());
+            if next_page_token.is_none() {
+                break;
+            }
+            thread::sleep(PAUSE_FETCH);
+        }
+        Ok(albums)
+    }
+
     pub fn fetch_sync(&self, limit: u32) -> Result<Vec<Media>> {
         let (tx, rx) = mpsc::channel();
-        self.fetch_media(limit, tx);
+        self.fetch_media(limit, tx)?;
         let mut media: Vec<Media> = Vec::new();
         for received in rx {
             media.extend(received);
@@ -275,68 +661,96 @@ impl MediaFetcher {
         Ok(media)
     }
 
-    pub fn fetch_media(&self, limit: u32, tx: Sender<Vec<Media>>) {
+    pub fn fetch_media(&self, limit: u32, tx: Sender<Vec<Media>>) -> Result<()> {
         let client = reqwest::blocking::Client::new();
         let uri = format!("{}/v1/mediaItems:search", self.base_uri);
-        // println!("self.access_token={}", self.access_token);
-        let bearer_token = format!("Bearer {}", self.access_token);
         let mut total = 0;
-        let limit_us: usize = limit.try_into().unwrap();
+        let limit_us = limit as usize;
         let mut next_page_token = Some(String::from(""));
         while next_page_token.is_some() && total < limit_us {
-            let next_album = self
-                .fetch_next(&client, &uri, &bearer_token, PAGE_SIZE, next_page_token)
-                .unwrap();
-            total += next_album.media_items.len();
-            next_page_token = next_album.next_page_token;
-            tx.send(next_album.media_items).unwrap();
+            let next_page = self.fetch_next(&client, &uri, PAGE_SIZE, next_page_token)?;
+            total += next_page.media_items.len();
+            next_page_token = next_page.next_page_token;
+            if tx.send(next_page.media_items).is_err() {
+                // the receiver has everything it wants
+                break;
+            }
             thread::sleep(PAUSE_FETCH);
         }
+        Ok(())
     }
 
     fn fetch_next(
         &self,
         client: &reqwest::blocking::Client,
         uri: &str,
-        bearer_token: &str,
         page_size: u32,
         next_page: Option<String>,
-    ) -> Result<Album> {
-        let mut body = json!({
-            "orderBy": "MediaMetadata.creation_time",
-            "filters": {
-                "dateFilter": {
-                    "ranges": [{"startDate": self.start_filter,
-                                "endDate": self.end_filter}]
+    ) -> Result<MediaPage> {
+        let mut body = match &self.album_id {
+            // albums can't be filtered or reordered
+            Some(album_id) => json!({
+                "albumId": album_id,
+                "pageSize": page_size
+            }),
+            None => {
+                let mut filters = json!({});
+                self.filters.apply(&mut filters);
+                let mut body = json!({ "pageSize": page_size });
+                // without a date filter Google can't order by creation time,
+                // but it also returns media dated before 1970
+                if !self.date_ranges.is_empty() {
+                    filters["dateFilter"] = json!({ "ranges": self.date_ranges });
+                    body["orderBy"] = json!("MediaMetadata.creation_time");
                 }
-            },
-            "pageSize": page_size
-        });
+                if filters != json!({}) {
+                    body["filters"] = filters;
+                }
+                body
+            }
+        };
 
         match next_page {
             None => (),
-            Some(token) if token == "" => (),
+            Some(token) if token.is_empty() => (),
             Some(token) => body["pageToken"] = json!(token),
         }
         // println!("{}", body.to_string());
-        let response = client
-            .post(uri)
-            .header("Authorization", bearer_token)
-            .body(body.to_string())
-            .send()
-            .unwrap();
-
-        // println!("album={}", response);
-        match response.status() {
-            StatusCode::OK => {
-                let album_raw = response.text().unwrap();
-                let album: Album = serde_json::from_str(&album_raw)?;
-                // println!("album.next_page_token={}", next_page_token);
-                Ok(album)
-            }
-            _ => {
-                panic!("Problem fetching metadata: {:?}", response);
-            }
+        let page_raw = self.send(|bearer_token| {
+            client
+                .post(uri)
+                .header("Authorization", bearer_token)
+                .body(body.to_string())
+                .send()
+        })?;
+        let page: MediaPage = serde_json::from_str(&page_raw)?;
+        // println!("page.next_page_token={}", next_page_token);
+        Ok(page)
+    }
+
+    /// Sends the request built by `send` with the given bearer token, retrying
+    /// as the retry policy allows, and returns the body of a successful
+    /// response.
+    fn send<F>(&self, send: F) -> Result<String>
+    where
+        F: Fn(&str) -> reqwest::Result<Response>,
+    {
+        let send_with = |access_token: String| {
+            let bearer_token = format!("Bearer {}", access_token);
+            self.retry_policy.send(|| send(&bearer_token))
+        };
+        let mut response = send_with(self.tokens.access_token()?)?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            // the token may have expired early or been revoked, so try once
+            // more with a fresh one before giving up
+            response = send_with(self.tokens.refresh()?)?;
+        }
+
+        let status = response.status();
+        let body = response.text()?;
+        match status {
+            StatusCode::OK => Ok(body),
+            _ => Err(Error::Status { status, body }),
         }
     }
 }
@@ -349,9 +763,9 @@ pub fn most_recent_date(mut base: PathBuf) -> Option<YearMonthDay> {
     let day = last_entry(base.as_path())?;
     // println!("ymd: {} {} {}", year, month, day);
     Some(YearMonthDay {
-        year: year.parse::<i32>().unwrap(),
-        month: month.parse::<u32>().unwrap(),
-        day: day.parse::<u32>().unwrap(),
+        year: year.parse::<i32>().ok()?,
+        month: month.parse::<u32>().ok()?,
+        day: day.parse::<u32>().ok()?,
     })
 }
 
@@ -360,98 +774,855 @@ fn last_entry(base: &Path) -> Option<String> {
     let paths = fs::read_dir(base);
     match paths {
         Ok(paths) => {
-            let mut sorted: Vec<_> = paths.map(|r| r.unwrap()).collect();
+            let mut sorted: Vec<_> = paths
+                .filter_map(|r| r.ok())
+                .filter(|entry| {
+                    // only the date directories, not the index, undated media
+                    // or album mirrors
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    name.chars().all(|c| c.is_ascii_digit())
+                })
+                .collect();
             sorted.sort_by_key(|entry| entry.path());
-            let result = if sorted.len() > 0 {
-                Some(
-                    sorted
-                        .last()
-                        .unwrap()
-                        .file_name()
-                        .to_string_lossy()
-                        .to_string(),
-                )
-            } else {
-                None
-            };
-            result
+            sorted
+                .last()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
         }
         _ => None,
     }
 }
 
 impl<'a> MediaWriter<'a> {
-    pub fn new(album_dir: &'a PathBuf) -> MediaWriter<'a> {
-        MediaWriter { album_dir }
+    pub fn new(album_dir: &'a PathBuf) -> Result<MediaWriter<'a>> {
+        let index = SyncIndex::open(album_dir)?;
+        remove_partial_downloads(album_dir)?;
+        Ok(MediaWriter {
+            album_dir,
+            retry_policy: RetryPolicy::default(),
+            jobs: 1,
+            json_sidecar: false,
+            xmp_sidecar: false,
+            embed_exif: false,
+            path_template: PathTemplate::default(),
+            album: None,
+            filtered: false,
+            collision_policy: CollisionPolicy::default(),
+            timezone: TimeZone::Utc,
+            index: Mutex::new(index),
+            claimed: Mutex::new(HashMap::new()),
+            taken: AtomicU32::new(0),
+        })
+    }
+
+    /// Sets how many media are downloaded at the same time.
+    pub fn with_jobs(mut self, jobs: usize) -> MediaWriter<'a> {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> MediaWriter<'a> {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Writes a `<filename>.json` file beside each download holding everything
+    /// Google returned for the media, since none of it survives in the file.
+    pub fn with_json_sidecar(mut self, json_sidecar: bool) -> MediaWriter<'a> {
+        self.json_sidecar = json_sidecar;
+        self
+    }
+
+    /// Writes a `<filename>.xmp` file beside each download with its creation
+    /// time, description, camera and dimensions, for photo managers to import.
+    pub fn with_xmp_sidecar(mut self, xmp_sidecar: bool) -> MediaWriter<'a> {
+        self.xmp_sidecar = xmp_sidecar;
+        self
+    }
+
+    /// Rewrites the EXIF block of each downloaded JPEG with the creation time,
+    /// description and camera Google has for it, since the files it serves
+    /// often lack them. The image data itself is left untouched.
+    pub fn with_embedded_exif(mut self, embed_exif: bool) -> MediaWriter<'a> {
+        self.embed_exif = embed_exif;
+        self
+    }
+
+    /// Sets where each media item is written to under the album directory.
+    pub fn with_path_template(mut self, path_template: PathTemplate) -> MediaWriter<'a> {
+        self.path_template = path_template;
+        self
+    }
+
+    /// Sets the album the media come from, whose title is used for the
+    /// `{album}` placeholder of the path template. Media written from an album
+    /// don't move where a sync of the whole library resumes from.
+    pub fn with_album(mut self, album: Option<AlbumInfo>) -> MediaWriter<'a> {
+        self.album = album;
+        self
+    }
+
+    /// Marks the media written as found by a search narrowed by filters, which
+    /// like media from an album don't move where a sync resumes from.
+    pub fn with_filtered(mut self, filtered: bool) -> MediaWriter<'a> {
+        self.filtered = filtered;
+        self
+    }
+
+    /// Sets what happens when media would be written to a path that already
+    /// belongs to a different item.
+    pub fn with_collision_policy(mut self, collision_policy: CollisionPolicy) -> MediaWriter<'a> {
+        self.collision_policy = collision_policy;
+        self
+    }
+
+    /// Sets the time zone creation times are converted to before the path
+    /// template splits them into dates. Defaults to UTC, which is what Google
+    /// reports them in.
+    pub fn with_timezone(mut self, timezone: TimeZone) -> MediaWriter<'a> {
+        self.timezone = timezone;
+        self
+    }
+
+    /// The creation date of the newest media recorded in the sync index, or
+    /// for media downloaded before there was an index, of the newest date
+    /// directory when they were laid out that way.
+    pub fn most_recent_date(&self) -> Option<YearMonthDay> {
+        let index = self.index();
+        if let Some(date) = index.most_recent_date(self.timezone, self.filtered) {
+            return Some(date);
+        }
+        // the directories also hold what filtered searches downloaded
+        let filtered = !self.filtered && index.entries().any(|entry| entry.filtered);
+        if self.path_template.is_default() && !filtered {
+            most_recent_date(self.album_dir.clone())
+        } else {
+            None
+        }
     }
 
     pub fn write_media(&self, media: Vec<Media>, limit: u32) -> Result<u64> {
         let (tx, rx) = mpsc::channel();
-        let t = thread::spawn(move || {
-            tx.send(media).unwrap();
-        });
-        t.join().unwrap();
+        tx.send(media)?;
+        drop(tx);
         self.write_channel(rx, limit)
     }
 
     pub fn write_channel(&self, rx: Receiver<Vec<Media>>, limit: u32) -> Result<u64> {
-        let path = PathBuf::from(self.album_dir);
-        let mut i = 0;
-        let mut written = 0;
+        self.write_batches(rx.iter(), limit)
+    }
+
+    /// Writes only the media that are not already downloaded, so that the limit
+    /// counts what was missing rather than everything that was received. Used
+    /// to backfill items that a date based resume would never see.
+    pub fn write_missing(&self, rx: Receiver<Vec<Media>>, limit: u32) -> Result<u64> {
+        let missing = rx.iter().map(|batch| {
+            batch
+                .into_iter()
+                .filter(|media| !self.is_downloaded(media))
+                .collect()
+        });
+        self.write_batches(missing, limit)
+    }
+
+    /// How many media the writes so far have taken on, counted the way their
+    /// `limit` counts them, so that a limit can be shared between writers.
+    pub fn taken(&self) -> u32 {
+        self.taken.load(Ordering::Relaxed)
+    }
+
+    /// Whether the index has a successful download for the media that is
+    /// still on disk.
+    pub fn is_downloaded(&self, media: &Media) -> bool {
+        match self.index().get(&media.id) {
+            Some(entry) => {
+                entry.status == DownloadStatus::Downloaded
+                    && self.album_dir.join(&entry.path).exists()
+            }
+            None => false,
+        }
+    }
+
+    fn index(&self) -> MutexGuard<'_, SyncIndex> {
+        // the index is only ever appended to, so it is still usable even if
+        // another writer panicked while holding it
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Hands the media out to `jobs` download workers. Items are numbered in
+    /// the order they are received, so the progress shows which of the limit
+    /// each one is even when they finish out of order. A failed item doesn't
+    /// stop the others, but the first failure is returned once all are done.
+    fn write_batches<I>(&self, batches: I, limit: u32) -> Result<u64>
+    where
+        I: Iterator<Item = Vec<Media>>,
+    {
         let to_display = if limit == u32::MAX {
             String::from("∞")
         } else {
             limit.to_string()
         };
-        let iter = rx.iter();
-        for next in iter {
-            let batch_result = next.iter().fold(0, |accum, media| {
+        let (work_tx, work_rx) = mpsc::sync_channel::<(u32, Media)>(self.jobs);
+        let work_rx = Mutex::new(work_rx);
+        let (done_tx, done_rx) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..self.jobs {
+                let work_rx = &work_rx;
+                let to_display = &to_display;
+                let done_tx = done_tx.clone();
+                scope.spawn(move || loop {
+                    let next = work_rx
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv();
+                    let (i, media) = match next {
+                        Ok(work) => work,
+                        Err(_) => break,
+                    };
+                    let progress = format!("[{}/{}]", i, to_display);
+                    let mut path = PathBuf::from(self.album_dir);
+                    let result = self.write_file(&mut path, &media, &progress);
+                    if let Err(err) = &result {
+                        println!("{}\tfailed {}: {}", progress, media.filename, err);
+                    }
+                    let _ = done_tx.send(result);
+                });
+            }
+
+            let mut i = 0;
+            'batches: for next in batches {
+                for media in next {
+                    if i == limit {
+                        break 'batches;
+                    }
+                    i += 1;
+                    if work_tx.send((i, media)).is_err() {
+                        break 'batches;
+                    }
+                    self.taken.fetch_add(1, Ordering::Relaxed);
+                }
                 if i == limit {
-                    return accum;
+                    break;
                 }
-                print!("[{}/{}]\t", i + 1, to_display);
-                i += 1;
-                let result = self.write_file(&mut path.clone(), media).unwrap();
-                return accum + result;
-            });
-            written += batch_result;
+            }
+            // closing the queue lets the workers finish up
+            drop(work_tx);
+        });
+        drop(done_tx);
+
+        let mut written = 0;
+        let mut first_error = None;
+        for result in done_rx {
+            match result {
+                Ok(file_len) => written += file_len,
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
         }
         // println!("written {}", written);
-        Ok(written)
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(written),
+        }
     }
 
-    fn write_file(&self, pathbuf: &mut PathBuf, media: &Media) -> Result<u64> {
-        let created_on = NaiveDateTime::parse_from_str(
-            media.media_metadata.creation_time.as_str(),
-            "%Y-%m-%dT%H:%M:%S%Z",
-        )
-        .unwrap();
+    fn write_file(&self, pathbuf: &mut PathBuf, media: &Media, progress: &str) -> Result<u64> {
+        let created_on = match media.created_on() {
+            Ok(created_on) => Some(created_on),
+            Err(err) => {
+                println!(
+                    "{}\twarning: {}, writing {} to {}",
+                    progress, err, media.filename, UNKNOWN_DATE_DIR
+                );
+                None
+            }
+        };
         // println!("created_on {}", created_on);
-        let year = created_on.year().to_string();
-        pathbuf.push(&year);
-        let month = created_on.month();
-        pathbuf.push(format!("{:02}", month));
-        let day = created_on.day();
-        pathbuf.push(format!("{:02}", day));
-        create_dir_all(pathbuf.as_path()).unwrap();
-        println!("{}/{}/{}", &year, month, day);
-        // println!("{}/{}/{} {}", &year, month, day, media.id);
-        pathbuf.push(encode(&media.filename).to_string());
+        let rendered = match created_on {
+            Some(created_on) => self.path_template.render(
+                media,
+                self.timezone.from_utc(created_on),
+                self.album.as_ref().map(|album| album.title.as_str()),
+            ),
+            None => self.path_template.render_undated(media),
+        };
+
+        if self.is_downloaded(media) {
+            // sidecars may have been turned on after the media was downloaded
+            let existing = self.index().get(&media.id).map(|entry| entry.path.clone());
+            if let Some(existing) = existing {
+                println!("{}\t{}", progress, existing.display());
+                self.write_sidecars(media, &self.album_dir.join(existing), false)?;
+            }
+            return Ok(0);
+        }
+        if self.index().get(&media.id).map(|entry| entry.status) == Some(DownloadStatus::Replaced) {
+            println!(
+                "{}\tskipping {}: overwritten by another item",
+                progress, media.filename
+            );
+            return Ok(0);
+        }
+        if self.index().get(&media.id).is_none() {
+            if let Some(legacy) = self.legacy_path(media, created_on, &rendered) {
+                // written by an older version of litho, so adopted where it is
+                println!("{}\t{}", progress, legacy.display());
+                pathbuf.push(&legacy);
+                self.adopt(media, legacy, pathbuf, created_on)?;
+                return Ok(0);
+            }
+        }
+        let (relative, displaced) = match self.claim_path(media, &rendered) {
+            Some(claimed) => claimed,
+            None => {
+                println!(
+                    "{}\tskipping {}: {} belongs to another item",
+                    progress,
+                    media.filename,
+                    rendered.display()
+                );
+                return Ok(0);
+            }
+        };
+        pathbuf.push(&relative);
+        if let Some(parent) = pathbuf.parent() {
+            create_dir_all(parent).map_err(|err| Error::io(parent, err))?;
+        }
+        println!("{}\t{}", progress, relative.display());
         // println!("path={:?}", pathbuf.as_path());
-        if pathbuf.exists() {
+        let known = self.index().get(&media.id).is_some();
+        // an empty file is what an interrupted download used to leave behind
+        let on_disk = fs::metadata(&pathbuf).map(|m| m.len() > 0).unwrap_or(false);
+        if !known && displaced.is_none() && on_disk {
+            // downloaded before there was an index, so adopt what is on disk
+            self.adopt(media, relative, pathbuf, created_on)?;
             return Ok(0);
         }
-        let mut file = File::create(pathbuf.as_path()).unwrap();
-        let download_url = format!("{}=d", media.base_url);
-        match reqwest::blocking::get(&download_url) {
-            Err(_) => Err(Error::IOError),
-            Ok(mut response) => {
-                let file_len = response.copy_to(&mut file).unwrap();
+        match media.processing_status() {
+            VideoProcessingStatus::Ready => {}
+            VideoProcessingStatus::Processing => {
+                // recorded so that the next sync resumes from here and tries again
+                println!(
+                    "{}\tskipping {}: still processing",
+                    progress, media.filename
+                );
+                self.record(
+                    media,
+                    relative,
+                    0,
+                    String::new(),
+                    DownloadStatus::Processing,
+                )?;
+                return Ok(0);
+            }
+            // a video that failed to process never becomes ready, so it must
+            // not hold back where the next sync resumes from
+            VideoProcessingStatus::Failed | VideoProcessingStatus::Unspecified => {
+                println!(
+                    "{}\tskipping {}: Google could not process it",
+                    progress, media.filename
+                );
+                self.record(media, relative, 0, String::new(), DownloadStatus::Failed)?;
+                return Ok(0);
+            }
+        }
+
+        let download_url = media.download_url();
+        let downloaded = download(&download_url, pathbuf, &self.retry_policy)
+            .and_then(|written| self.embed_exif(media, pathbuf, written))
+            .and_then(|written| {
+                if let Some(created_on) = created_on {
+                    set_file_times(pathbuf, created_on)?;
+                }
+                Ok(written)
+            });
+        match downloaded {
+            Ok((file_len, checksum)) => {
+                if let Some(displaced) = displaced {
+                    self.displace(&displaced, &relative)?;
+                }
+                self.record(
+                    media,
+                    relative,
+                    file_len,
+                    checksum,
+                    DownloadStatus::Downloaded,
+                )?;
+                self.write_sidecars(media, pathbuf, true)?;
                 thread::sleep(PAUSE_WRITE);
                 Ok(file_len)
             }
+            Err(err) => {
+                self.record(media, relative, 0, String::new(), DownloadStatus::Failed)?;
+                Err(err)
+            }
         }
     }
+
+    /// Picks the path the media is written to, settling a collision with a
+    /// different item already at `rendered` by the collision policy. Also
+    /// returns the id of the item whose file there is to be replaced, or None
+    /// when the media should be skipped.
+    fn claim_path(&self, media: &Media, rendered: &Path) -> Option<(PathBuf, Option<String>)> {
+        let mut claimed = self.claimed.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut displaced = None;
+        let path = match self.owner(rendered, &claimed) {
+            Some(id) if id != media.id => match self.collision_policy {
+                CollisionPolicy::Skip => return None,
+                CollisionPolicy::Overwrite => {
+                    displaced = Some(id);
+                    rendered.to_path_buf()
+                }
+                CollisionPolicy::IdInName => {
+                    collision::with_suffix(rendered, &format!("_{}", media.id))
+                }
+                CollisionPolicy::Suffix => (2..)
+                    .map(|n| collision::with_suffix(rendered, &format!("~{}", n)))
+                    .find(|candidate| match self.owner(candidate, &claimed) {
+                        Some(id) => id == media.id,
+                        None => !self.album_dir.join(candidate).exists(),
+                    })?,
+            },
+            // a file that no item is known to own is adopted by this one
+            _ => rendered.to_path_buf(),
+        };
+        claimed.insert(path.clone(), media.id.clone());
+        Some((path, displaced))
+    }
+
+    /// Where an older version of litho may have written the media, when it
+    /// isn't at `rendered`: with its file name URL encoded, or filed under its
+    /// date in UTC rather than in the configured time zone. Only a file that
+    /// no other item is known to own is returned.
+    fn legacy_path(
+        &self,
+        media: &Media,
+        created_on: Option<NaiveDateTime>,
+        rendered: &Path,
+    ) -> Option<PathBuf> {
+        let created_on = created_on?;
+        if self.album_dir.join(rendered).exists() {
+            return None;
+        }
+        let album = self.album.as_ref().map(|album| album.title.as_str());
+        let local = self.timezone.from_utc(created_on);
+        let candidates = vec![
+            self.path_template.render_legacy(media, created_on, album),
+            self.path_template.render(media, created_on, album),
+            self.path_template.render_legacy(media, local, album),
+        ];
+        let mut claimed = self.claimed.lock().unwrap_or_else(PoisonError::into_inner);
+        let legacy = candidates.into_iter().find(|candidate| {
+            let on_disk = fs::metadata(self.album_dir.join(candidate))
+                .map(|m| m.len() > 0)
+                .unwrap_or(false);
+            candidate != rendered
+                && on_disk
+                && self
+                    .owner(candidate, &claimed)
+                    .is_none_or(|id| id == media.id)
+        })?;
+        claimed.insert(legacy.clone(), media.id.clone());
+        Some(legacy)
+    }
+
+    /// Records a file that is already on disk as the media's download.
+    fn adopt(
+        &self,
+        media: &Media,
+        relative: PathBuf,
+        path: &Path,
+        created_on: Option<NaiveDateTime>,
+    ) -> Result<()> {
+        let (size, checksum) = checksum_file(path)?;
+        if let Some(created_on) = created_on {
+            set_file_times(path, created_on)?;
+        }
+        self.record(media, relative, size, checksum, DownloadStatus::Downloaded)?;
+        self.write_sidecars(media, path, false)
+    }
+
+    /// Records that the item `id` no longer has its file at `path`, which was
+    /// overwritten by another, so that it is neither verified against the new
+    /// file nor downloaded over it again.
+    fn displace(&self, id: &str, path: &Path) -> Result<()> {
+        let mut index = self.index();
+        let entry = match index.get(id) {
+            Some(entry) if entry.path == path => entry.clone(),
+            _ => return Ok(()),
+        };
+        index.record(IndexEntry {
+            status: DownloadStatus::Replaced,
+            ..entry
+        })
+    }
+
+    /// The id of the media stored at `path`, according to the index, the
+    /// downloads in progress or a JSON sidecar.
+    fn owner(&self, path: &Path, claimed: &HashMap<PathBuf, String>) -> Option<String> {
+        if let Some(entry) = self.index().owner(path) {
+            return Some(entry.id.clone());
+        }
+        if let Some(id) = claimed.get(path) {
+            return Some(id.clone());
+        }
+        let sidecar = sidecar_path(&self.album_dir.join(path), "json");
+        let json = fs::read_to_string(sidecar).ok()?;
+        let media: serde_json::Value = serde_json::from_str(&json).ok()?;
+        media["id"].as_str().map(String::from)
+    }
+
+    /// Embeds the metadata into a photo that was just downloaded, returning the
+    /// length and checksum of the file as it ends up.
+    fn embed_exif(
+        &self,
+        media: &Media,
+        path: &Path,
+        written: (u64, String),
+    ) -> Result<(u64, String)> {
+        if self.embed_exif && !media.is_video() && exif::embed(path, media, self.timezone)? {
+            checksum_file(path)
+        } else {
+            Ok(written)
+        }
+    }
+
+    /// Writes the enabled sidecars for the media downloaded to `path`. Unless
+    /// `replace` is set, sidecars that already exist are left alone.
+    fn write_sidecars(&self, media: &Media, path: &Path, replace: bool) -> Result<()> {
+        if self.json_sidecar {
+            let sidecar = sidecar_path(path, "json");
+            if replace || !sidecar.exists() {
+                let json = serde_json::to_string_pretty(media)?;
+                fs::write(&sidecar, json).map_err(|err| Error::io(&sidecar, err))?;
+            }
+        }
+        if self.xmp_sidecar {
+            let sidecar = sidecar_path(path, "xmp");
+            if replace || !sidecar.exists() {
+                let xmp = xmp::to_xmp(media)?;
+                fs::write(&sidecar, xmp).map_err(|err| Error::io(&sidecar, err))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        media: &Media,
+        path: PathBuf,
+        size: u64,
+        checksum: String,
+        status: DownloadStatus,
+    ) -> Result<()> {
+        self.index().record(IndexEntry {
+            id: media.id.clone(),
+            filename: media.filename.clone(),
+            path,
+            size,
+            checksum,
+            creation_time: media.media_metadata.creation_time.clone(),
+            status,
+            album_id: self.album.as_ref().map(|album| album.id.clone()),
+            contributor: media
+                .contributor_info
+                .as_ref()
+                .and_then(|contributor| contributor.display_name.clone()),
+            filtered: self.filtered,
+        })
+    }
+}
+
+/// The path of a sidecar for the file at `path`, which keeps the file's own
+/// extension so that `photo.jpg` is described by `photo.jpg.json`.
+fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+/// The name a media item is stored under, which for videos has its extension
+/// changed to match the mime type when the original name says otherwise.
+fn local_filename(media: &Media) -> String {
+    if !media.is_video() {
+        return media.filename.clone();
+    }
+    let extensions = match video_extensions(&media.mime_type) {
+        Some(extensions) => extensions,
+        None => return media.filename.clone(),
+    };
+    let path = Path::new(&media.filename);
+    let matches = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false);
+    if matches {
+        return media.filename.clone();
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| media.filename.clone());
+    format!("{}.{}", stem, extensions[0])
+}
+
+/// The file extensions used for a video mime type, preferred one first.
+fn video_extensions(mime_type: &str) -> Option<&'static [&'static str]> {
+    let extensions: &'static [&'static str] = match mime_type {
+        "video/mp4" => &["mp4", "m4v"],
+        "video/x-m4v" => &["m4v", "mp4"],
+        "video/quicktime" => &["mov", "qt"],
+        "video/3gpp" => &["3gp"],
+        "video/3gpp2" => &["3g2"],
+        "video/x-msvideo" => &["avi"],
+        "video/x-matroska" => &["mkv"],
+        "video/webm" => &["webm"],
+        "video/mpeg" => &["mpg", "mpeg"],
+        "video/mp2t" => &["mts", "m2ts", "ts"],
+        "video/x-ms-wmv" => &["wmv"],
+        _ => return None,
+    };
+    Some(extensions)
+}
+
+/// Streams `url` into the file at `path`, returning its length and checksum.
+///
+/// The body is written to a hidden sibling first and only renamed into place
+/// once all of it has arrived, so an interrupted download never leaves a
+/// truncated file where a finished one is expected.
+fn download(url: &str, path: &Path, retry_policy: &RetryPolicy) -> Result<(u64, String)> {
+    let partial = partial_path(path);
+    // a body cut off part way starts over with a fresh request
+    let written = retry_policy.retry_interrupted(|| {
+        let mut response = retry_policy.send(|| reqwest::blocking::get(url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text()?;
+            return Err(Error::Status { status, body });
+        }
+        write_partial(&mut response, &partial).inspect_err(|_| {
+            let _ = fs::remove_file(&partial);
+        })
+    })?;
+    fs::rename(&partial, path).map_err(|err| Error::io(path, err))?;
+    Ok(written)
+}
+
+fn write_partial(response: &mut Response, partial: &Path) -> Result<(u64, String)> {
+    let content_length = response.content_length();
+    let file = File::create(partial).map_err(|err| Error::io(partial, err))?;
+    let mut checksum = ChecksumWriter::new(file);
+    // copied by hand so that failing to read the body can be told apart from
+    // failing to write the file
+    let mut buffer = vec![0; 64 * 1024];
+    let mut file_len = 0;
+    loop {
+        let read = match response.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(source) => {
+                return Err(Error::Interrupted {
+                    path: PathBuf::from(partial),
+                    source,
+                })
+            }
+        };
+        checksum
+            .write_all(&buffer[..read])
+            .map_err(|err| Error::io(partial, err))?;
+        file_len += read as u64;
+    }
+    if let Some(expected) = content_length {
+        if expected != file_len {
+            return Err(Error::Incomplete {
+                path: PathBuf::from(partial),
+                expected,
+                actual: file_len,
+            });
+        }
+    }
+    checksum
+        .get_ref()
+        .sync_all()
+        .map_err(|err| Error::io(partial, err))?;
+    Ok((file_len, checksum.finish()))
+}
+
+/// Where the download for `path` is written while it is in progress.
+fn partial_path(path: &Path) -> PathBuf {
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}{}", filename, PARTIAL_SUFFIX))
+}
+
+/// Removes downloads that were left behind by a run that was interrupted.
+fn remove_partial_downloads(dir: &Path) -> Result<()> {
+    walk_files(dir, &mut |path, name| {
+        if name.starts_with('.') && name.ends_with(PARTIAL_SUFFIX) {
+            fs::remove_file(path).map_err(|err| Error::io(path, err))?;
+        }
+        Ok(())
+    })
+}
+
+/// Calls `visit` with the path and name of every file under `album_dir`,
+/// except for those in the index directory and the links of album mirrors,
+/// whether symbolic links or the hard links the mirror's manifest lists.
+fn walk_files(album_dir: &Path, visit: &mut dyn FnMut(&Path, &str) -> Result<()>) -> Result<()> {
+    let links = mirror::mirror_links(album_dir)?;
+    walk_dir(album_dir, &links, visit)
+}
+
+fn walk_dir(
+    dir: &Path,
+    links: &HashSet<PathBuf>,
+    visit: &mut dyn FnMut(&Path, &str) -> Result<()>,
+) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    for entry in entries.filter_map(|r| r.ok()) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().map(|t| t.is_symlink()).unwrap_or(false) || links.contains(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            if name != INDEX_DIR {
+                walk_dir(&path, links, visit)?;
+            }
+        } else {
+            visit(&path, &name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the modification and access times of every downloaded file under
+/// `album_dir` to when its media was created, returning how many were set.
+///
+/// The creation time comes from the sync index, or for files downloaded
+/// before there was one, from their JSON sidecar. Files whose creation time
+/// can't be parsed are left alone.
+pub fn fix_times(album_dir: &Path) -> Result<usize> {
+    let index = SyncIndex::open(album_dir)?;
+    let mut fixed = 0;
+    let mut indexed = HashSet::new();
+    let downloaded = index
+        .entries()
+        .filter(|entry| entry.status == DownloadStatus::Downloaded);
+    for entry in downloaded {
+        let path = album_dir.join(&entry.path);
+        if let (true, Ok(created_on)) = (path.exists(), parse_creation_time(&entry.creation_time)) {
+            set_file_times(&path, created_on)?;
+            fixed += 1;
+        }
+        indexed.insert(path);
+    }
+
+    walk_files(album_dir, &mut |sidecar, name| {
+        let media_path = match name.strip_suffix(".json") {
+            Some(media_name) => sidecar.with_file_name(media_name),
+            None => return Ok(()),
+        };
+        if indexed.contains(&media_path) || !media_path.exists() {
+            return Ok(());
+        }
+        let json = fs::read_to_string(sidecar).map_err(|err| Error::io(sidecar, err))?;
+        // Takeout and other tools leave sidecars of their own next to media
+        let media: Media = match serde_json::from_str(&json) {
+            Ok(media) => media,
+            Err(err) => {
+                println!("warning: skipping {}: {}", sidecar.display(), err);
+                return Ok(());
+            }
+        };
+        if let Ok(created_on) = media.created_on() {
+            set_file_times(&media_path, created_on)?;
+            fixed += 1;
+        }
+        Ok(())
+    })?;
+    Ok(fixed)
+}
+
+/// Dates the file at `path` to when its media was created, so that it sorts
+/// by when it was taken rather than when it was downloaded.
+fn set_file_times(path: &Path, created_on: NaiveDateTime) -> Result<()> {
+    let since_epoch = Duration::new(
+        created_on.timestamp().unsigned_abs(),
+        created_on.timestamp_subsec_nanos(),
+    );
+    let time = if created_on.timestamp() < 0 {
+        UNIX_EPOCH - since_epoch
+    } else {
+        UNIX_EPOCH + since_epoch
+    };
+    let times = FileTimes::new().set_accessed(time).set_modified(time);
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_times(times))
+        .map_err(|err| Error::io(path, err))
+}
+
+/// Parses a creation time as Google formats it, an RFC 3339 timestamp such as
+/// `2018-03-03T10:11:12.345Z`, into UTC. Fractional seconds are kept and
+/// timestamps with an offset other than `Z` are converted.
+fn parse_creation_time(creation_time: &str) -> Result<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(creation_time)
+        .map(|created_on| created_on.naive_utc())
+        .map_err(|source| Error::CreationTime {
+            value: String::from(creation_time),
+            source,
+        })
+}
+
+fn checksum_file(path: &Path) -> Result<(u64, String)> {
+    let mut file = File::open(path).map_err(|err| Error::io(path, err))?;
+    let mut checksum = ChecksumWriter::new(io::sink());
+    let size = io::copy(&mut file, &mut checksum).map_err(|err| Error::io(path, err))?;
+    Ok((size, checksum.finish()))
+}
+
+/// Passes writes through to the inner writer while computing the SHA-256 of
+/// everything written.
+struct ChecksumWriter<W> {
+    inner: W,
+    sha: Sha256,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> ChecksumWriter<W> {
+        ChecksumWriter {
+            inner,
+            sha: Sha256::new(),
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    fn finish(self) -> String {
+        format!("{:x}", self.sha.finalize())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sha.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 #[cfg(test)]
@@ -464,8 +1635,11 @@ mod tests {
 
     use crate::YearMonthDay;
 
+    use chrono::NaiveDate;
+
     use super::extract_code;
     use super::most_recent_date;
+    use super::parse_creation_time;
 
     #[test]
     fn test_most_recent() -> Result<(), Box<dyn std::error::Error>> {
@@ -474,6 +1648,8 @@ mod tests {
         create_all_dirs(temp_dir.path(), "2023", "09", "29");
         create_all_dirs(temp_dir.path(), "2023", "08", "30");
         create_all_dirs(temp_dir.path(), "2022", "07", "28");
+        fs::create_dir_all(temp_dir.path().join("photos/unknown-date"))?;
+        fs::create_dir_all(temp_dir.path().join("photos/albums/Camping"))?;
         let mut base = PathBuf::from(temp_dir.path());
         base.push("photos");
         let result = most_recent_date(base).unwrap();
@@ -497,6 +1673,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_most_recent_ignores_hidden() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        create_all_dirs(temp_dir.path(), "2023", "09", "30");
+        let mut base = PathBuf::from(temp_dir.path());
+        base.push("photos");
+        fs::create_dir_all(base.join(".litho"))?;
+        fs::create_dir_all(base.join("2023").join(".litho"))?;
+        let result = most_recent_date(base).unwrap();
+        let expected = YearMonthDay {
+            year: 2023,
+            month: 9,
+            day: 30,
+        };
+        assert_eq!(expected, result);
+        Ok(())
+    }
+
     fn create_all_dirs(base: &Path, year: &str, month: &str, day: &str) {
         let mut temp_path = PathBuf::from(base);
         temp_path.push("photos");
@@ -519,8 +1713,27 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_extract_code_missing() {
-        extract_code("http://127.0.0.1:7878/?error=barf&scope=some_scope");
+        let result = extract_code("http://127.0.0.1:7878/?error=barf&scope=some_scope");
+        assert_eq!(None, result)
+    }
+
+    #[test]
+    fn test_parse_creation_time() {
+        let expected = NaiveDate::from_ymd(2018, 3, 3).and_hms(10, 11, 12);
+        assert_eq!(
+            expected,
+            parse_creation_time("2018-03-03T10:11:12Z").unwrap()
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2018, 3, 3).and_hms_milli(10, 11, 12, 345),
+            parse_creation_time("2018-03-03T10:11:12.345Z").unwrap()
+        );
+        assert_eq!(
+            expected,
+            parse_creation_time("2018-03-03T12:11:12+02:00").unwrap()
+        );
+        assert!(parse_creation_time("2018-03-03 10:11:12").is_err());
+        assert!(parse_creation_time("").is_err());
     }
 }
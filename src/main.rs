@@ -1,12 +1,18 @@
-use chrono::{Datelike, Local};
-use litho::YearMonthDay;
+use chrono::NaiveDate;
+use litho::{DownloadStatus, Problem, TokenProvider};
 use structopt::StructOpt;
 
 use std::env;
+use std::error::Error;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::mpsc;
 use std::thread;
 
+const REFRESH_URI: &str = "https://oauth2.googleapis.com/token";
+const PHOTOS_URI: &str = "https://photoslibrary.googleapis.com";
+
 #[derive(StructOpt)]
 /// A utility for fetching photos from Google.
 ///
@@ -16,73 +22,699 @@ use std::thread;
 /// $CWD/photos/yyyy/mm/dd/file-name.jpg
 ///
 /// where the directory structure is based off the date the photo was taken (or
-/// uploaded on) and the original file name. The Google Photo API may not return
-/// the media at the original resolution and will not include all orginal metadata
-/// like the geolocation. Use Google Take Out to fetch the original if a true back
-/// up is desired.
+/// uploaded on) and the original file name. Both the directory and the layout
+/// beneath it can be changed with --output and --path-template. The Google
+/// Photo API may not return the media at the original resolution and will not
+/// include all orginal metadata like the geolocation. Use Google Take Out to
+/// fetch the original if a true back up is desired.
 struct Cli {
-    /// an optional limit of the number of photos to fetch
-    #[structopt(short, long)]
-    limit: Option<u32>,
+    /// the directory media are downloaded into [default: $CWD/photos]
+    #[structopt(short, long, global = true, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// the time zone of the dates media are filed by, e.g. UTC or +05:30
+    #[structopt(long, global = true, default_value = "local")]
+    timezone: litho::TimeZone,
+
+    /// use the Google account and output directory of a profile added with
+    /// litho profiles add
+    #[structopt(long, global = true)]
+    profile: Option<String>,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Authorize access to a Google Photos library and store the refresh token
+    Auth,
+    /// Download media from Google Photos into the photos directory
+    Sync {
+        /// an optional limit of the number of photos to fetch
+        #[structopt(short, long)]
+        limit: Option<u32>,
+
+        /// walk the entire library and download any media that are missing locally,
+        /// including those created before the most recently downloaded item
+        #[structopt(short, long)]
+        backfill: bool,
+
+        /// only download media created on or after this date, given as YYYY-MM-DD
+        /// or as a number of days, weeks, months or years ago like 30d [default:
+        /// the day of the most recently downloaded item]
+        #[structopt(long, value_name = "date", conflicts_with_all = &["range", "album"])]
+        since: Option<String>,
+
+        /// only download media created on or before this date [default: today]
+        #[structopt(long, value_name = "date", conflicts_with_all = &["range", "album"])]
+        until: Option<String>,
+
+        /// only download media created between two dates, given as since..until
+        /// where either may be left out; can be given up to 5 times
+        #[structopt(
+            long,
+            value_name = "since..until",
+            number_of_values = 1,
+            conflicts_with = "album"
+        )]
+        range: Vec<String>,
+
+        /// the number of times a request is attempted before giving up when Google
+        /// is rate limiting or unavailable
+        #[structopt(long, default_value = "5")]
+        retries: u32,
+
+        /// the number of media to download at the same time
+        #[structopt(short, long, default_value = "1")]
+        jobs: usize,
+
+        /// write a <file-name>.json file next to each download with the metadata
+        /// Google has for it
+        #[structopt(long)]
+        json_sidecar: bool,
+
+        /// write a <file-name>.xmp file next to each download for photo managers
+        /// like darktable and digiKam
+        #[structopt(long)]
+        xmp_sidecar: bool,
+
+        /// rewrite the EXIF data of downloaded JPEGs with the creation time,
+        /// description and camera that Google has for them
+        #[structopt(long)]
+        embed_exif: bool,
+
+        /// where each media is written under the output directory, using the
+        /// placeholders {year}, {month}, {day}, {hour}, {minute}, {second},
+        /// {filename}, {id}, {mime}, {camera_make}, {camera_model} and {album}
+        /// [default: {year}/{month}/{day}/{filename}, or
+        /// albums/{album}/{filename} with --album]
+        #[structopt(long)]
+        path_template: Option<String>,
+
+        /// download the media in the album with this id or title instead of
+        /// the whole library
+        #[structopt(long)]
+        album: Option<String>,
+
+        /// after downloading, lay out every album under albums/ as links to the
+        /// downloaded media
+        #[structopt(long, possible_values = litho::LinkKind::VARIANTS)]
+        mirror_albums: Option<litho::LinkKind>,
+
+        /// also download the media in albums others have shared, and look in
+        /// them for --album and --mirror-albums
+        #[structopt(long)]
+        include_shared: bool,
+
+        /// only download media in this category, such as documents; can be
+        /// given more than once
+        #[structopt(long, value_name = "category", number_of_values = 1, possible_values = litho::ContentCategory::VARIANTS, hide_possible_values = true)]
+        include_category: Vec<litho::ContentCategory>,
+
+        /// skip media in this category, such as screenshots; can be given more
+        /// than once
+        #[structopt(long, value_name = "category", number_of_values = 1, possible_values = litho::ContentCategory::VARIANTS, hide_possible_values = true)]
+        exclude_category: Vec<litho::ContentCategory>,
+
+        /// only download photos or only videos
+        #[structopt(long, default_value = "all", possible_values = litho::MediaType::VARIANTS)]
+        media_type: litho::MediaType,
+
+        /// only download media marked as favorites
+        #[structopt(long)]
+        favorites: bool,
+
+        /// also download media that have been archived
+        #[structopt(long)]
+        include_archived: bool,
+
+        /// what to do when different media would be written to the same path
+        #[structopt(long, default_value = "suffix", possible_values = litho::CollisionPolicy::VARIANTS)]
+        on_collision: litho::CollisionPolicy,
+
+        /// also replace the characters in file names that Windows doesn't allow,
+        /// such as when backing up to an SMB share
+        #[structopt(long)]
+        windows_safe_names: bool,
+    },
+    /// List the albums in the library
+    Albums {
+        /// also list the albums others have shared
+        #[structopt(long)]
+        include_shared: bool,
+    },
+    /// Compare what has been downloaded locally with what is in the library
+    Status,
+    /// Check that downloaded media still match their recorded size and checksum
+    Verify,
+    /// Set the modification time of downloaded media to when they were created
+    FixTimes,
+    /// Rename media downloaded with URL encoded names to readable ones
+    MigrateNames {
+        /// also replace the characters in file names that Windows doesn't allow
+        #[structopt(long)]
+        windows_safe_names: bool,
+    },
+    /// Remove the stored refresh token
+    Logout,
+    /// Manage the profiles used to back up more than one Google account
+    Profiles(ProfilesCommand),
 }
 
-fn main() -> Result<(), litho::Error> {
+#[derive(StructOpt)]
+enum ProfilesCommand {
+    /// List the profiles and the directories they download into
+    List,
+    /// Add a profile that downloads into --output [default: $CWD/photos-<name>]
+    Add { name: String },
+    /// Remove a profile and its stored refresh token, leaving its media alone
+    Remove { name: String },
+}
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
     let client_id = env!("CLIENT_ID");
     let client_secret = env!("CLIENT_SECRET");
 
     let args = Cli::from_args();
 
-    let refresh_uri = "https://oauth2.googleapis.com/token";
+    let profile = match &args.profile {
+        Some(name) => match profiles()?.get(name) {
+            Some(profile) => Some(profile.clone()),
+            None => {
+                return Err(format!(
+                    "no profile is named {}, add it with litho profiles add {}",
+                    name, name
+                )
+                .into())
+            }
+        },
+        None => None,
+    };
+
     let username = whoami::username();
-    let keyring = keyring::Keyring::new(&client_id, &username);
+    let keyring_entry = keyring_user(&username, args.profile.as_deref());
+    let keyring = keyring::Keyring::new(client_id, &keyring_entry);
+    let token_fetcher = litho::TokenFetcher::new(client_id, client_secret, REFRESH_URI);
+
+    let photos_dir = match (&args.output, &profile) {
+        (Some(output), _) => output.clone(),
+        (None, Some(profile)) => profile.output.clone(),
+        (None, None) => env::current_dir()?.join("photos"),
+    };
+
+    match args.cmd {
+        Command::Auth => {
+            let to_store = token_fetcher.fetch_refresh()?;
+            keyring.set_password(&to_store)?;
+            println!("Authorization stored.");
+        }
+        Command::Sync {
+            limit,
+            backfill,
+            since,
+            until,
+            range,
+            retries,
+            jobs,
+            json_sidecar,
+            xmp_sidecar,
+            embed_exif,
+            path_template,
+            on_collision,
+            windows_safe_names,
+            album,
+            mirror_albums,
+            include_shared,
+            include_category,
+            exclude_category,
+            media_type,
+            favorites,
+            include_archived,
+        } => {
+            // parsed before signing in, so that a mistyped argument doesn't
+            // wait on the browser first
+            let path_template = path_template
+                .as_deref()
+                .map(litho::PathTemplate::parse)
+                .transpose()?;
+            fs::create_dir_all(&photos_dir)?;
+            let retry_policy = litho::RetryPolicy {
+                max_attempts: retries.max(1),
+                ..litho::RetryPolicy::default()
+            };
+            let filters = litho::SearchFilters {
+                include_categories: include_category,
+                exclude_categories: exclude_category,
+                media_type,
+                favorites,
+                include_archived,
+            };
+            let timezone = args.timezone;
+            let media_writer = |album: Option<litho::AlbumInfo>| -> Result<litho::MediaWriter<'_>> {
+                let path_template = match (&path_template, &album) {
+                    (Some(path_template), _) => path_template.clone(),
+                    (None, Some(_)) => litho::PathTemplate::parse(litho::ALBUM_TEMPLATE)?,
+                    (None, None) => litho::PathTemplate::parse(litho::DEFAULT_TEMPLATE)?,
+                };
+                Ok(litho::MediaWriter::new(&photos_dir)?
+                    .with_retry_policy(retry_policy.clone())
+                    .with_jobs(jobs)
+                    .with_json_sidecar(json_sidecar)
+                    .with_xmp_sidecar(xmp_sidecar)
+                    .with_embedded_exif(embed_exif)
+                    .with_path_template(path_template.with_windows_safe_names(windows_safe_names))
+                    .with_collision_policy(on_collision)
+                    .with_timezone(timezone)
+                    .with_album(album))
+            };
+            // albums can't be searched by date, so only the library's dates
+            // are checked before signing in
+            let (library_writer, date_ranges) = match &album {
+                Some(_) => (None, Vec::new()),
+                None => {
+                    // only the library can be filtered, not albums
+                    let library_writer = media_writer(None)?.with_filtered(filters.narrows());
+                    let date_ranges = date_ranges(
+                        &library_writer,
+                        since.as_deref(),
+                        until.as_deref(),
+                        &range,
+                        backfill,
+                        timezone,
+                    )?;
+                    (Some(library_writer), date_ranges)
+                }
+            };
+            let tokens = token_provider(&keyring, token_fetcher)?;
+            let album = match album {
+                Some(album) => Some(find_album(&tokens, &album, include_shared)?),
+                None => None,
+            };
+            let library_writer = match library_writer {
+                Some(library_writer) => library_writer,
+                None => media_writer(album.clone())?,
+            };
+            // the limit is shared with the shared albums synced after
+            let mut remaining = limit;
+            let taken = sync(
+                &tokens,
+                library_writer,
+                limit,
+                backfill,
+                date_ranges,
+                album.as_ref().map(|album| album.id.clone()),
+                |media_fetcher| {
+                    media_fetcher
+                        .with_retry_policy(retry_policy.clone())
+                        .with_filters(filters)
+                },
+            )?;
+            remaining = remaining.map(|remaining| remaining.saturating_sub(taken));
+            if include_shared && album.is_none() && remaining != Some(0) {
+                let shared_albums =
+                    litho::MediaFetcher::new(String::from(PHOTOS_URI), &tokens, Vec::new())
+                        .with_retry_policy(retry_policy.clone())
+                        .fetch_shared_albums()?;
+                for shared in shared_albums {
+                    println!("Shared album {}", shared.title);
+                    let album_id = shared.id.clone();
+                    // only what is missing, as anything the user added to the
+                    // album is already in their library
+                    let taken = sync(
+                        &tokens,
+                        media_writer(Some(shared))?,
+                        remaining,
+                        true,
+                        Vec::new(),
+                        Some(album_id),
+                        |media_fetcher| media_fetcher.with_retry_policy(retry_policy.clone()),
+                    )?;
+                    remaining = remaining.map(|remaining| remaining.saturating_sub(taken));
+                    if remaining == Some(0) {
+                        break;
+                    }
+                }
+            }
+            if let Some(link_kind) = mirror_albums {
+                let album_mirror = litho::AlbumMirror::new(&photos_dir, link_kind)
+                    .with_windows_safe_names(windows_safe_names);
+                mirror(&tokens, album_mirror, retry_policy, include_shared)?;
+            }
+        }
+        Command::Albums { include_shared } => {
+            let tokens = token_provider(&keyring, token_fetcher)?;
+            for album in albums(&tokens, include_shared)? {
+                let count = album
+                    .media_items_count
+                    .map(|count| count.to_string())
+                    .unwrap_or_default();
+                let shared = if album.share_info.is_some() {
+                    "\tshared"
+                } else {
+                    ""
+                };
+                println!("{}\t{}\t{}{}", album.id, count, album.title, shared);
+            }
+        }
+        Command::Status => {
+            let tokens = token_provider(&keyring, token_fetcher)?;
+            fs::create_dir_all(&photos_dir)?;
+            status(tokens, &photos_dir)?;
+        }
+        Command::Verify => {
+            verify(&photos_dir)?;
+        }
+        Command::FixTimes => {
+            let fixed = litho::fix_times(&photos_dir)?;
+            println!("Set the times of {} media", fixed);
+        }
+        Command::MigrateNames { windows_safe_names } => {
+            let renamed = litho::migrate_names(&photos_dir, windows_safe_names)?;
+            for (from, to) in &renamed {
+                println!("{}\t-> {}", from.display(), to.display());
+            }
+            println!("Renamed {} files", renamed.len());
+        }
+        Command::Logout => match keyring.delete_password() {
+            Ok(_) => println!("Logged out."),
+            Err(_) => println!("No stored authorization found."),
+        },
+        Command::Profiles(ProfilesCommand::List) => {
+            let profiles = profiles()?;
+            for profile in profiles.profiles() {
+                println!("{}\t{}", profile.name, profile.output.display());
+            }
+        }
+        Command::Profiles(ProfilesCommand::Add { name }) => {
+            let output = match &args.output {
+                Some(output) => output.clone(),
+                None => PathBuf::from(format!("photos-{}", name)),
+            };
+            // kept absolute so the profile works from any directory
+            let output = env::current_dir()?.join(output);
+            profiles()?.add(litho::Profile {
+                name: name.clone(),
+                output: output.clone(),
+            })?;
+            println!(
+                "Added profile {} downloading into {}. Authorize it with litho --profile {} auth",
+                name,
+                output.display(),
+                name
+            );
+        }
+        Command::Profiles(ProfilesCommand::Remove { name }) => {
+            match profiles()?.remove(&name)? {
+                Some(removed) => {
+                    let keyring_entry = keyring_user(&username, Some(&name));
+                    // there is no token to remove if it was never authorized
+                    let _ = keyring::Keyring::new(client_id, &keyring_entry).delete_password();
+                    println!(
+                        "Removed profile {}, its media are still in {}",
+                        name,
+                        removed.output.display()
+                    );
+                }
+                None => return Err(format!("no profile is named {}", name).into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The profiles kept in the user's configuration directory.
+fn profiles() -> Result<litho::Profiles> {
+    let config_dir = dirs_next::config_dir().ok_or("unable to find the configuration directory")?;
+    Ok(litho::Profiles::open(
+        &config_dir.join("litho").join("profiles.json"),
+    )?)
+}
+
+/// The user the refresh token is stored under in the keyring. The account
+/// used without a profile keeps the entry it has always had.
+fn keyring_user(username: &str, profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => format!("{}:{}", username, profile),
+        None => String::from(username),
+    }
+}
+
+/// Builds a provider that keeps the access token fresh for the whole run,
+/// authorizing first if there is no stored refresh token.
+fn token_provider(
+    keyring: &keyring::Keyring,
+    token_fetcher: litho::TokenFetcher<'static>,
+) -> Result<litho::RefreshingTokenProvider<'static>> {
+    let refresh_token = match keyring.get_password() {
+        Ok(refresh_token) => refresh_token,
+        Err(_) => {
+            println!("Token not found, authorizing");
+            let to_store = token_fetcher.fetch_refresh()?;
+            // println!("to_store={}", to_store);
+            keyring.set_password(&to_store)?;
+            to_store
+        }
+    };
+
+    let tokens = litho::RefreshingTokenProvider::new(token_fetcher, refresh_token);
+    // fail before doing any work if the refresh token is no longer valid
+    tokens.access_token()?;
+    Ok(tokens)
+}
+
+/// Downloads the media in the library created in `date_ranges`, or in the
+/// album with `album_id`, that `configure` leaves the fetcher searching for.
+/// Returns how many of the `limit` were used.
+fn sync<'a>(
+    tokens: &'a litho::RefreshingTokenProvider<'static>,
+    media_writer: litho::MediaWriter,
+    limit: Option<u32>,
+    backfill: bool,
+    date_ranges: Vec<litho::DateRange>,
+    album_id: Option<String>,
+    configure: impl FnOnce(litho::MediaFetcher<'a>) -> litho::MediaFetcher<'a>,
+) -> Result<u32> {
+    let media_fetcher = litho::MediaFetcher::new(String::from(PHOTOS_URI), tokens, date_ranges);
+    let media_fetcher = configure(media_fetcher);
+    let media_fetcher = match &album_id {
+        Some(album_id) => media_fetcher.with_album(album_id),
+        None => media_fetcher,
+    };
+    let limit = limit.unwrap_or(u32::MAX);
+
+    // when backfilling the limit applies to the missing media, so the whole
+    // library needs to be walked to find them
+    let fetch_limit = if backfill { u32::MAX } else { limit };
+
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        let helper = scope.spawn(move || media_fetcher.fetch_media(fetch_limit, tx));
+
+        let written = if backfill {
+            media_writer.write_missing(rx, limit)
+        } else {
+            media_writer.write_channel(rx, limit)
+        };
+
+        // a failed fetch hangs up the channel, so report it ahead of the writer
+        helper.join().unwrap()?;
+        written?;
+        Ok(media_writer.taken())
+    })
+}
+
+/// The dates a sync of the library searches: the `ranges` given, otherwise
+/// from `since` until `until`. Without `since` a sync resumes from the day of
+/// the most recently downloaded item, unless `until` is given, which searches
+/// everything up to it. A backfill without either searches the whole library,
+/// which is no dates at all.
+fn date_ranges(
+    media_writer: &litho::MediaWriter,
+    since: Option<&str>,
+    until: Option<&str>,
+    ranges: &[String],
+    backfill: bool,
+    timezone: litho::TimeZone,
+) -> Result<Vec<litho::DateRange>> {
+    let earliest = NaiveDate::from_ymd(1970, 1, 1);
+    let today = timezone.today();
+    if let Some(extra) = ranges.get(litho::MAX_RANGES) {
+        return Err(litho::Error::Date {
+            date: extra.clone(),
+            reason: format!("at most {} ranges can be given", litho::MAX_RANGES),
+        }
+        .into());
+    }
+    if !ranges.is_empty() {
+        return Ok(ranges
+            .iter()
+            .map(|range| litho::DateRange::parse(range, earliest, today))
+            .collect::<std::result::Result<_, litho::Error>>()?);
+    }
+
+    let since = match since {
+        Some(since) => litho::parse_date(since, today)?,
+        None if backfill && until.is_none() => return Ok(Vec::new()),
+        None if backfill || until.is_some() => earliest,
+        None => media_writer
+            .most_recent_date()
+            .and_then(|date| NaiveDate::from_ymd_opt(date.year, date.month, date.day))
+            .unwrap_or(earliest),
+    };
+    let until = match until {
+        Some(until) => litho::parse_date(until, today)?,
+        None => today,
+    };
+    Ok(vec![litho::DateRange::between(since, until)?])
+}
 
-    if env::var("CLEAR_TOKEN").is_ok() {
-        keyring.delete_password().unwrap();
+/// Mirrors every album in the library as links to the media that have been
+/// downloaded.
+fn mirror(
+    tokens: &litho::RefreshingTokenProvider<'static>,
+    album_mirror: litho::AlbumMirror,
+    retry_policy: litho::RetryPolicy,
+    include_shared: bool,
+) -> Result<()> {
+    let media_fetcher = |album_id: &str| {
+        litho::MediaFetcher::new(String::from(PHOTOS_URI), tokens, Vec::new())
+            .with_retry_policy(retry_policy.clone())
+            .with_album(album_id)
+    };
+    let mut memberships = Vec::new();
+    for album in albums(tokens, include_shared)? {
+        let ids = media_fetcher(&album.id)
+            .fetch_sync(u32::MAX)?
+            .into_iter()
+            .map(|media| media.id)
+            .collect();
+        memberships.push((album, ids));
     }
+    let changes = album_mirror.mirror(&memberships)?;
+    println!(
+        "Mirrored {} albums, {} links added and {} removed",
+        memberships.len(),
+        changes.added,
+        changes.removed
+    );
+    Ok(())
+}
 
-    let token_fetcher = litho::TokenFetcher::new(&client_id, &client_secret, &refresh_uri);
-
-    let refresh_token: Result<String, litho::Error> = keyring.get_password().or_else(|_| {
-        println!("Token not found, authorizing");
-        let to_store = token_fetcher.fetch_refresh().unwrap();
-        // println!("to_store={}", to_store);
-        keyring.set_password(&to_store).unwrap();
-        Ok(to_store)
-    });
-
-    let access_token = token_fetcher.fetch_access(&refresh_token.unwrap()).unwrap();
-    let mut photos_dir = env::current_dir().unwrap();
-    photos_dir.push("photos");
-    fs::create_dir_all(&photos_dir).unwrap();
-
-    let most_recent_path = photos_dir.clone();
-    let start_filter = litho::most_recent_date(most_recent_path).unwrap_or(YearMonthDay {
-        year: 1970,
-        month: 1,
-        day: 1,
-    });
-    let today = Local::today();
-    let end_filter = YearMonthDay {
-        year: today.year(),
-        month: today.month(),
-        day: today.day(),
+/// The albums in the library, followed by those shared with the user when
+/// `include_shared` is set.
+fn albums(
+    tokens: &litho::RefreshingTokenProvider<'static>,
+    include_shared: bool,
+) -> Result<Vec<litho::AlbumInfo>> {
+    let media_fetcher = litho::MediaFetcher::new(String::from(PHOTOS_URI), tokens, Vec::new());
+    let mut albums = media_fetcher.fetch_albums()?;
+    if include_shared {
+        // albums the user shared are listed both ways
+        for shared in media_fetcher.fetch_shared_albums()? {
+            if !albums.iter().any(|album| album.id == shared.id) {
+                albums.push(shared);
+            }
+        }
+    }
+    Ok(albums)
+}
+
+/// Finds the album with the id or title `album`, which has to be the only one
+/// with that title.
+fn find_album(
+    tokens: &litho::RefreshingTokenProvider<'static>,
+    album: &str,
+    include_shared: bool,
+) -> Result<litho::AlbumInfo> {
+    let albums = albums(tokens, include_shared)?;
+    if let Some(found) = albums.iter().find(|candidate| candidate.id == album) {
+        return Ok(found.clone());
+    }
+    let mut titled = albums
+        .into_iter()
+        .filter(|candidate| candidate.title == album);
+    match (titled.next(), titled.next()) {
+        (Some(found), None) => Ok(found),
+        (Some(_), Some(_)) => Err(format!(
+            "there is more than one album titled {}, use its id from litho albums",
+            album
+        )
+        .into()),
+        (None, _) => Err(format!("no album has the id or title {}", album).into()),
+    }
+}
+
+fn status(tokens: litho::RefreshingTokenProvider<'static>, photos_dir: &Path) -> Result<()> {
+    let index = litho::SyncIndex::open(photos_dir)?;
+    let count = |status| {
+        index
+            .entries()
+            .filter(|entry| entry.status == status)
+            .count()
     };
-    let media_fetcher = litho::MediaFetcher::new(
-        String::from("https://photoslibrary.googleapis.com"),
-        access_token,
-        start_filter,
-        end_filter,
+    println!(
+        "Local:  {} downloaded, {} failed, {} processing, {} replaced",
+        count(DownloadStatus::Downloaded),
+        count(DownloadStatus::Failed),
+        count(DownloadStatus::Processing),
+        count(DownloadStatus::Replaced)
     );
-    let limit = args.limit.unwrap_or(u32::MAX);
 
+    let media_fetcher = litho::MediaFetcher::new(String::from(PHOTOS_URI), tokens, Vec::new());
     let (tx, rx) = mpsc::channel();
-    let helper = thread::spawn(move || {
-        media_fetcher.fetch_media(limit, tx);
-    });
+    let helper = thread::spawn(move || media_fetcher.fetch_media(u32::MAX, tx));
 
-    let media_writer = litho::MediaWriter::new(&photos_dir);
-    media_writer.write_channel(rx, limit).unwrap();
+    let mut remote = 0;
+    let mut missing = 0;
+    for batch in rx {
+        remote += batch.len();
+        missing += batch
+            .iter()
+            .filter(|media| match index.get(&media.id) {
+                Some(entry) => !matches!(
+                    entry.status,
+                    DownloadStatus::Downloaded | DownloadStatus::Replaced
+                ),
+                None => true,
+            })
+            .count();
+    }
+    helper.join().unwrap()?;
+    println!("Remote: {} media, {} not downloaded", remote, missing);
+    Ok(())
+}
 
-    helper.join().unwrap();
+fn verify(photos_dir: &Path) -> Result<()> {
+    let index = litho::SyncIndex::open(photos_dir)?;
+    let failures = index.verify(photos_dir)?;
+    for failure in &failures {
+        let reason = match &failure.problem {
+            Problem::Missing => String::from("missing"),
+            Problem::SizeMismatch { actual } => {
+                format!("expected {} bytes, found {}", failure.entry.size, actual)
+            }
+            Problem::ChecksumMismatch { .. } => String::from("checksum mismatch"),
+        };
+        println!("{}\t{}", failure.entry.path.display(), reason);
+    }
+    let downloaded = index
+        .entries()
+        .filter(|entry| entry.status == DownloadStatus::Downloaded)
+        .count();
+    println!(
+        "Verified {} media, {} problems found",
+        downloaded,
+        failures.len()
+    );
     Ok(())
 }
@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::collision::with_suffix;
+use crate::index::INDEX_DIR;
+use crate::{partial_path, sanitize_filename, AlbumInfo, DownloadStatus, Error, Result, SyncIndex};
+
+/// The directory under the output directory that albums are mirrored into.
+pub const MIRROR_DIR: &str = "albums";
+
+/// The links litho has made, so that it only ever removes its own.
+const MANIFEST_FILE: &str = "albums.json";
+
+/// How the files in an album mirror point at the downloaded media.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// relative symbolic links, which survive the output directory moving
+    Symlink,
+    /// hard links, for file systems or tools that don't follow symbolic links
+    Hardlink,
+}
+
+impl LinkKind {
+    pub const VARIANTS: &'static [&'static str] = &["symlink", "hardlink"];
+}
+
+impl FromStr for LinkKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> std::result::Result<LinkKind, String> {
+        match kind {
+            "symlink" => Ok(LinkKind::Symlink),
+            "hardlink" => Ok(LinkKind::Hardlink),
+            _ => Err(format!("unknown link kind {}", kind)),
+        }
+    }
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            LinkKind::Symlink => "symlink",
+            LinkKind::Hardlink => "hardlink",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// What a call to `AlbumMirror::mirror` changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MirrorChanges {
+    pub added: usize,
+    pub removed: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct MirrorLink {
+    /// the link, relative to the output directory
+    path: PathBuf,
+    /// the downloaded media it points at, relative to the output directory
+    target: PathBuf,
+    id: String,
+}
+
+/// Lays out an `albums/<title>/` directory for each album holding links to
+/// the media already downloaded into the output directory, so that albums can
+/// be browsed without storing any media twice.
+pub struct AlbumMirror<'a> {
+    album_dir: &'a Path,
+    link_kind: LinkKind,
+    windows_safe: bool,
+}
+
+impl<'a> AlbumMirror<'a> {
+    pub fn new(album_dir: &'a Path, link_kind: LinkKind) -> AlbumMirror<'a> {
+        AlbumMirror {
+            album_dir,
+            link_kind,
+            windows_safe: false,
+        }
+    }
+
+    /// Also replaces the characters in album titles that Windows doesn't allow.
+    pub fn with_windows_safe_names(mut self, windows_safe: bool) -> AlbumMirror<'a> {
+        self.windows_safe = windows_safe;
+        self
+    }
+
+    /// Brings the mirror in line with `albums`, each given with the ids of the
+    /// media in it. Links are added for media that have been downloaded and
+    /// the links made by an earlier run are removed once their media leave
+    /// the album, or the album goes away. Files litho didn't link are never
+    /// touched.
+    pub fn mirror(&self, albums: &[(AlbumInfo, Vec<String>)]) -> Result<MirrorChanges> {
+        let index = SyncIndex::open(self.album_dir)?;
+        let manifest = self.read_manifest()?;
+        let wanted = self.wanted_links(&index, albums);
+
+        let mut changes = MirrorChanges::default();
+        let mut kept = Vec::new();
+        for link in manifest {
+            let full_path = self.album_dir.join(&link.path);
+            let still_wanted = wanted.get(&link.path) == Some(&link);
+            if still_wanted && self.is_linked(&link) {
+                kept.push(link);
+                continue;
+            }
+            match fs::remove_file(&full_path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(Error::io(&full_path, err)),
+            }
+            if !still_wanted {
+                changes.removed += 1;
+                if let Some(parent) = full_path.parent() {
+                    // only succeeds once the album is empty
+                    let _ = fs::remove_dir(parent);
+                }
+            }
+        }
+
+        let linked: HashSet<PathBuf> = kept.iter().map(|link| link.path.clone()).collect();
+        for link in wanted.into_values() {
+            if linked.contains(&link.path) {
+                continue;
+            }
+            let full_path = self.album_dir.join(&link.path);
+            if fs::symlink_metadata(&full_path).is_ok() {
+                println!(
+                    "skipping {}: a file litho didn't link is in the way",
+                    link.path.display()
+                );
+                continue;
+            }
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent).map_err(|err| Error::io(parent, err))?;
+            }
+            self.link(&link).map_err(|err| Error::io(&full_path, err))?;
+            changes.added += 1;
+            kept.push(link);
+        }
+
+        kept.sort_by(|a, b| a.path.cmp(&b.path));
+        self.write_manifest(&kept)?;
+        Ok(changes)
+    }
+
+    /// The link each downloaded media in each album should have, keyed by the
+    /// path of the link.
+    fn wanted_links(
+        &self,
+        index: &SyncIndex,
+        albums: &[(AlbumInfo, Vec<String>)],
+    ) -> BTreeMap<PathBuf, MirrorLink> {
+        let mut wanted = BTreeMap::new();
+        let mut album_dirs = HashSet::new();
+        for (album, ids) in albums {
+            let title = match sanitize_filename(album.title.trim(), self.windows_safe) {
+                title if title.is_empty() || title == "." || title == ".." => album.id.clone(),
+                title => title,
+            };
+            let mut dir = Path::new(MIRROR_DIR).join(&title);
+            if !album_dirs.insert(dir.clone()) {
+                // more than one album has this title
+                dir = Path::new(MIRROR_DIR).join(format!("{} ({})", title, album.id));
+                album_dirs.insert(dir.clone());
+            }
+
+            for id in ids {
+                let entry = match index.get(id) {
+                    Some(entry) if entry.status == DownloadStatus::Downloaded => entry,
+                    _ => continue,
+                };
+                if !self.album_dir.join(&entry.path).exists() {
+                    continue;
+                }
+                let name = entry.path.file_name().unwrap_or_default();
+                let mut path = dir.join(name);
+                if path == entry.path {
+                    // synced from the album straight into its mirror
+                    continue;
+                }
+                let mut n = 2;
+                while wanted.contains_key(&path) {
+                    path = with_suffix(&dir.join(name), &format!("~{}", n));
+                    n += 1;
+                }
+                let link = MirrorLink {
+                    path: path.clone(),
+                    target: entry.path.clone(),
+                    id: id.clone(),
+                };
+                wanted.insert(path, link);
+            }
+        }
+        wanted
+    }
+
+    /// Whether the link on disk still points at its media.
+    fn is_linked(&self, link: &MirrorLink) -> bool {
+        let path = self.album_dir.join(&link.path);
+        match self.link_kind {
+            LinkKind::Symlink => fs::read_link(&path)
+                .map(|target| target == relative_target(&link.path, &link.target))
+                .unwrap_or(false),
+            LinkKind::Hardlink => {
+                let target = self.album_dir.join(&link.target);
+                match (fs::symlink_metadata(&path), fs::metadata(&target)) {
+                    (Ok(linked), Ok(target)) => {
+                        !linked.file_type().is_symlink() && same_file(&linked, &target)
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    fn link(&self, link: &MirrorLink) -> io::Result<()> {
+        let path = self.album_dir.join(&link.path);
+        match self.link_kind {
+            LinkKind::Symlink => symlink(&relative_target(&link.path, &link.target), &path),
+            LinkKind::Hardlink => fs::hard_link(self.album_dir.join(&link.target), &path),
+        }
+    }
+
+    fn read_manifest(&self) -> Result<Vec<MirrorLink>> {
+        read_manifest(self.album_dir)
+    }
+
+    fn write_manifest(&self, links: &[MirrorLink]) -> Result<()> {
+        let path = manifest_path(self.album_dir);
+        // written aside and renamed so that an interrupted run never loses
+        // track of the links it made
+        let partial = partial_path(&path);
+        fs::write(&partial, serde_json::to_string_pretty(links)?)
+            .map_err(|err| Error::io(&partial, err))?;
+        fs::rename(&partial, &path).map_err(|err| Error::io(&path, err))
+    }
+}
+
+/// The links the album mirror under `album_dir` is made of, which are not
+/// media of their own.
+pub(crate) fn mirror_links(album_dir: &Path) -> Result<HashSet<PathBuf>> {
+    Ok(read_manifest(album_dir)?
+        .into_iter()
+        .map(|link| album_dir.join(link.path))
+        .collect())
+}
+
+fn manifest_path(album_dir: &Path) -> PathBuf {
+    album_dir.join(INDEX_DIR).join(MANIFEST_FILE)
+}
+
+fn read_manifest(album_dir: &Path) -> Result<Vec<MirrorLink>> {
+    let path = manifest_path(album_dir);
+    match fs::read_to_string(&path) {
+        Ok(json) => Ok(serde_json::from_str(&json)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(Error::io(&path, err)),
+    }
+}
+
+/// The path from the directory holding `link` to `target`, when both are
+/// relative to the same directory.
+fn relative_target(link: &Path, target: &Path) -> PathBuf {
+    let depth = link
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .count()
+        })
+        .unwrap_or(0);
+    let mut relative: PathBuf = (0..depth).map(|_| Component::ParentDir).collect();
+    relative.push(target);
+    relative
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.len() == b.len() && a.modified().ok() == b.modified().ok()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::path::{Path, PathBuf};
+
+    use super::{relative_target, LinkKind};
+
+    #[test]
+    fn test_relative_target() {
+        assert_eq!(
+            PathBuf::from("../../2014/10/02/test.jpg"),
+            relative_target(
+                Path::new("albums/Camping/test.jpg"),
+                Path::new("2014/10/02/test.jpg")
+            )
+        );
+        assert_eq!(
+            PathBuf::from("test.jpg"),
+            relative_target(Path::new("link.jpg"), Path::new("test.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_parse_link_kind() {
+        for variant in LinkKind::VARIANTS {
+            let kind: LinkKind = variant.parse().unwrap();
+            assert_eq!(*variant, kind.to_string());
+        }
+        assert!("copy".parse::<LinkKind>().is_err());
+    }
+}
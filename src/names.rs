@@ -0,0 +1,156 @@
+use urlencoding::{decode, encode};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::collision::with_suffix;
+use crate::{sidecar_path, walk_files, Error, IndexEntry, Result, SyncIndex};
+
+/// The sidecars that are renamed along with the media they describe.
+const SIDECARS: &[&str] = &["json", "xmp"];
+
+/// Names Windows reserves for devices, whatever the extension.
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes `name` usable as a file name while keeping it as readable as
+/// possible: spaces, parentheses and unicode are left alone and only the
+/// characters a file system would reject are replaced with `_`.
+///
+/// That is just `/` and NUL, unless `windows_safe` is set, in which case the
+/// characters and names that Windows and SMB shares reject are replaced too.
+pub fn sanitize_filename(name: &str, windows_safe: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\0' => '_',
+            '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' if windows_safe => '_',
+            c if windows_safe && c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if windows_safe {
+        // Windows quietly drops trailing dots and spaces
+        let trimmed = sanitized.trim_end_matches(&['.', ' '][..]).len();
+        sanitized.replace_range(trimmed.., &"_".repeat(sanitized.len() - trimmed));
+        let stem = sanitized.split('.').next().unwrap_or_default();
+        if RESERVED.contains(&stem.to_uppercase().as_str()) {
+            sanitized.insert(0, '_');
+        }
+    }
+    sanitized
+}
+
+/// Renames the files under `album_dir` that earlier versions of litho wrote
+/// with URL encoded names, like `Beach%20Day.jpg`, to their sanitized form,
+/// along with their sidecars and index entries. Returns what was renamed to
+/// what, relative to `album_dir`.
+///
+/// Only names that are exactly what the encoding would have produced are
+/// touched, so files that just happen to contain a `%` are left alone.
+pub fn migrate_names(album_dir: &Path, windows_safe: bool) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut index = SyncIndex::open(album_dir)?;
+    let mut encoded = Vec::new();
+    walk_files(album_dir, &mut |path, name| {
+        if !is_sidecar(path) && decoded(name).is_some() {
+            encoded.push(path.to_path_buf());
+        }
+        Ok(())
+    })?;
+    encoded.sort();
+
+    let mut renamed = Vec::new();
+    for from in encoded {
+        let name = from.file_name().unwrap_or_default().to_string_lossy();
+        let name = sanitize_filename(&decoded(&name).unwrap_or_default(), windows_safe);
+        let mut to = from.with_file_name(&name);
+        let mut n = 2;
+        while to.exists() {
+            to = with_suffix(&from.with_file_name(&name), &format!("~{}", n));
+            n += 1;
+        }
+
+        fs::rename(&from, &to).map_err(|err| Error::io(&from, err))?;
+        for extension in SIDECARS {
+            let sidecar = sidecar_path(&from, extension);
+            if sidecar.exists() {
+                let renamed_sidecar = sidecar_path(&to, extension);
+                fs::rename(&sidecar, &renamed_sidecar).map_err(|err| Error::io(&sidecar, err))?;
+            }
+        }
+
+        let from = from.strip_prefix(album_dir).unwrap_or(&from).to_path_buf();
+        let to = to.strip_prefix(album_dir).unwrap_or(&to).to_path_buf();
+        if let Some(entry) = index.owner(&from).cloned() {
+            index.record(IndexEntry {
+                path: to.clone(),
+                ..entry
+            })?;
+        }
+        renamed.push((from, to));
+    }
+    Ok(renamed)
+}
+
+/// The name an encoded file name was encoded from, when it was encoded.
+fn decoded(name: &str) -> Option<String> {
+    if !name.contains('%') {
+        return None;
+    }
+    let decoded = decode(name).ok()?;
+    if encode(&decoded) == name {
+        Some(decoded.into_owned())
+    } else {
+        None
+    }
+}
+
+/// Whether `path` is the sidecar of a file next to it.
+fn is_sidecar(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    match extension {
+        Some(extension) if SIDECARS.contains(&extension) => path.with_extension("").exists(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{decoded, sanitize_filename};
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(
+            "Beach Day (1).jpg",
+            sanitize_filename("Beach Day (1).jpg", false)
+        );
+        assert_eq!(
+            "Été à Paris.jpg",
+            sanitize_filename("Été à Paris.jpg", false)
+        );
+        assert_eq!("0_1.jpg", sanitize_filename("0/1.jpg", false));
+        assert_eq!("a_b.jpg", sanitize_filename("a\0b.jpg", false));
+        assert_eq!("what?.jpg", sanitize_filename("what?.jpg", false));
+
+        assert_eq!("what_.jpg", sanitize_filename("what?.jpg", true));
+        assert_eq!("a_b_c_.jpg", sanitize_filename("a:b\\c|.jpg", true));
+        assert_eq!("notes_", sanitize_filename("notes.", true));
+        assert_eq!("_con.jpg", sanitize_filename("con.jpg", true));
+        assert_eq!("console.jpg", sanitize_filename("console.jpg", true));
+    }
+
+    #[test]
+    fn test_decoded() {
+        assert_eq!(
+            Some(String::from("Beach Day (1).jpg")),
+            decoded("Beach%20Day%20%281%29.jpg")
+        );
+        assert_eq!(None, decoded("Beach Day.jpg"));
+        // not something the encoding would have produced
+        assert_eq!(None, decoded("100%.jpg"));
+        assert_eq!(None, decoded("Beach%20Day (1).jpg"));
+    }
+}
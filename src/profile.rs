@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{partial_path, Error, Result};
+
+/// A Google account that is backed up separately from the others, into its
+/// own output directory and so with its own index.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub name: String,
+    /// the directory the account's media are downloaded into
+    pub output: PathBuf,
+}
+
+/// The profiles that have been added, kept as a JSON file so that they are
+/// remembered between runs.
+#[derive(Debug)]
+pub struct Profiles {
+    path: PathBuf,
+    profiles: Vec<Profile>,
+}
+
+impl Profiles {
+    /// Reads the profiles kept at `path`, which don't need to exist yet.
+    pub fn open(path: &Path) -> Result<Profiles> {
+        let profiles = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(Error::io(path, err)),
+        };
+        Ok(Profiles {
+            path: path.to_path_buf(),
+            profiles,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = &Profile> {
+        self.profiles.iter()
+    }
+
+    /// Adds `profile` and saves the profiles. Names are limited to letters,
+    /// digits, `-` and `_`, as they become part of the keyring entry.
+    pub fn add(&mut self, profile: Profile) -> Result<()> {
+        let invalid = |reason: &str| Error::Profile {
+            name: profile.name.clone(),
+            reason: String::from(reason),
+        };
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if profile.name.is_empty() || !profile.name.chars().all(valid) {
+            return Err(invalid("names may only contain letters, digits, - and _"));
+        }
+        if self.get(&profile.name).is_some() {
+            return Err(invalid("a profile with this name already exists"));
+        }
+        if self.profiles().any(|other| other.output == profile.output) {
+            return Err(invalid(
+                "another profile already downloads into this directory",
+            ));
+        }
+        self.profiles.push(profile);
+        self.save()
+    }
+
+    /// Removes the profile named `name` and saves the profiles, returning the
+    /// profile when there was one. Nothing that was downloaded is touched.
+    pub fn remove(&mut self, name: &str) -> Result<Option<Profile>> {
+        let position = match self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+        {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let removed = self.profiles.remove(position);
+        self.save()?;
+        Ok(Some(removed))
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|err| Error::io(parent, err))?;
+        }
+        let partial = partial_path(&self.path);
+        fs::write(&partial, serde_json::to_string_pretty(&self.profiles)?)
+            .map_err(|err| Error::io(&partial, err))?;
+        fs::rename(&partial, &self.path).map_err(|err| Error::io(&self.path, err))
+    }
+}
@@ -0,0 +1,159 @@
+use chrono::{Datelike, Duration, Months, NaiveDate};
+use serde::Serialize;
+
+use crate::{Error, Result, YearMonthDay};
+
+/// The most date ranges a single search of the library accepts.
+pub const MAX_RANGES: usize = 5;
+
+/// The dates a search of the library covers, including both the first and
+/// the last day.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateRange {
+    #[serde(rename = "startDate")]
+    pub start: YearMonthDay,
+    #[serde(rename = "endDate")]
+    pub end: YearMonthDay,
+}
+
+impl DateRange {
+    pub fn new(start: YearMonthDay, end: YearMonthDay) -> DateRange {
+        DateRange { start, end }
+    }
+
+    /// Parses a range written as `<since>..<until>`, where either end is a
+    /// date as `parse_date` accepts it. An end left out is filled in with
+    /// `earliest` or `today`.
+    pub fn parse(range: &str, earliest: NaiveDate, today: NaiveDate) -> Result<DateRange> {
+        let (since, until) = range.split_once("..").ok_or_else(|| Error::Date {
+            date: String::from(range),
+            reason: String::from("ranges are written as <since>..<until>"),
+        })?;
+        let since = match since {
+            "" => earliest,
+            since => parse_date(since, today)?,
+        };
+        let until = match until {
+            "" => today,
+            until => parse_date(until, today)?,
+        };
+        DateRange::between(since, until)
+    }
+
+    /// The range from `since` to `until`, which must not be before `since`.
+    pub fn between(since: NaiveDate, until: NaiveDate) -> Result<DateRange> {
+        if since > until {
+            return Err(Error::Date {
+                date: format!("{}..{}", since, until),
+                reason: String::from("the range ends before it starts"),
+            });
+        }
+        Ok(DateRange::new(since.into(), until.into()))
+    }
+}
+
+impl From<NaiveDate> for YearMonthDay {
+    fn from(date: NaiveDate) -> YearMonthDay {
+        YearMonthDay {
+            year: date.year(),
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+}
+
+/// Parses a date written as `2022-09-01`, or relative to `today` as a number
+/// of days, weeks, months or years ago, like `30d`, `2w`, `6m` or `1y`.
+pub fn parse_date(date: &str, today: NaiveDate) -> Result<NaiveDate> {
+    let invalid = |reason: &str| Error::Date {
+        date: String::from(date),
+        reason: String::from(reason),
+    };
+
+    if let Ok(parsed) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Ok(parsed);
+    }
+    let unit = date
+        .chars()
+        .last()
+        .ok_or_else(|| invalid("expected a date"))?;
+    let count: u32 = date[..date.len() - unit.len_utf8()].parse().map_err(|_| {
+        invalid("expected YYYY-MM-DD or a number of days, weeks, months or years ago like 30d")
+    })?;
+    let ago = match unit {
+        'd' => today.checked_sub_signed(Duration::days(count.into())),
+        'w' => today.checked_sub_signed(Duration::weeks(count.into())),
+        'm' => today.checked_sub_months(Months::new(count)),
+        'y' => count
+            .checked_mul(12)
+            .and_then(|months| today.checked_sub_months(Months::new(months))),
+        _ => return Err(invalid("relative dates end in d, w, m or y")),
+    };
+    ago.ok_or_else(|| invalid("too long ago"))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+
+    use super::{parse_date, DateRange};
+
+    #[test]
+    fn test_parse_date() {
+        let today = NaiveDate::from_ymd(2022, 9, 22);
+        assert_eq!(
+            NaiveDate::from_ymd(2019, 6, 1),
+            parse_date("2019-06-01", today).unwrap()
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2022, 8, 23),
+            parse_date("30d", today).unwrap()
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2022, 9, 8),
+            parse_date("2w", today).unwrap()
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2022, 3, 22),
+            parse_date("6m", today).unwrap()
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 9, 22),
+            parse_date("1y", today).unwrap()
+        );
+        assert_eq!(today, parse_date("0d", today).unwrap());
+
+        assert!(parse_date("", today).is_err());
+        assert!(parse_date("d", today).is_err());
+        assert!(parse_date("30x", today).is_err());
+        assert!(parse_date("2019-02-30", today).is_err());
+        assert!(parse_date("yesterday", today).is_err());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let earliest = NaiveDate::from_ymd(1970, 1, 1);
+        let today = NaiveDate::from_ymd(2022, 9, 22);
+        let range = |since: NaiveDate, until: NaiveDate| DateRange::new(since.into(), until.into());
+
+        assert_eq!(
+            range(
+                NaiveDate::from_ymd(2019, 6, 1),
+                NaiveDate::from_ymd(2019, 8, 31)
+            ),
+            DateRange::parse("2019-06-01..2019-08-31", earliest, today).unwrap()
+        );
+        assert_eq!(
+            range(NaiveDate::from_ymd(2022, 8, 23), today),
+            DateRange::parse("30d..", earliest, today).unwrap()
+        );
+        assert_eq!(
+            range(earliest, NaiveDate::from_ymd(2000, 1, 1)),
+            DateRange::parse("..2000-01-01", earliest, today).unwrap()
+        );
+
+        assert!(DateRange::parse("2019-06-01", earliest, today).is_err());
+        assert!(DateRange::parse("2019-08-31..2019-06-01", earliest, today).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::blocking::Response;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+
+use std::thread;
+use std::time::Duration;
+
+use crate::{Error, Result};
+
+/// How requests to Google are retried when they fail for reasons that are
+/// likely to go away on their own, like rate limiting or an overloaded server.
+///
+/// Each retry waits for a jittered exponential backoff starting at
+/// `base_delay` and capped at `max_delay`, unless the response says how long
+/// to wait with a `Retry-After` header, which is capped at `max_delay` too.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// the total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sends the request built by `send` until it either succeeds, fails with
+    /// a permanent error or runs out of attempts. The last response is returned
+    /// whatever its status, so callers still need to check it.
+    pub(crate) fn send<F>(&self, mut send: F) -> Result<Response>
+    where
+        F: FnMut() -> reqwest::Result<Response>,
+    {
+        let mut attempt = 1;
+        loop {
+            let retry_after = match send() {
+                Ok(response) if attempt < self.max_attempts && is_retryable(response.status()) => {
+                    retry_after(&response)
+                }
+                Err(err)
+                    if attempt < self.max_attempts && (err.is_connect() || err.is_timeout()) =>
+                {
+                    None
+                }
+                result => return Ok(result?),
+            };
+            let delay = self.delay(attempt, retry_after);
+            println!(
+                "Attempt {} of {} failed, retrying in {:?}",
+                attempt, self.max_attempts, delay
+            );
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Runs `download` again when it fails part way through the body, such as
+    /// when the connection is reset or closed early. `send` only covers
+    /// getting a response, so these failures would otherwise be final.
+    pub(crate) fn retry_interrupted<T, F>(&self, mut download: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            match download() {
+                Err(err) if attempt < self.max_attempts && is_interrupted(&err) => {
+                    let delay = self.backoff(attempt);
+                    println!(
+                        "Attempt {} of {} failed ({}), retrying in {:?}",
+                        attempt, self.max_attempts, err, delay
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// How long to wait before the next attempt: what the server asked for,
+    /// but never longer than `max_delay`, or else the backoff.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(retry_after) => retry_after.min(self.max_delay),
+            None => self.backoff(attempt),
+        }
+    }
+
+    /// A random delay between half of and the full exponential backoff for the
+    /// given attempt, so that concurrent requests don't retry in lock step.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        backoff.mul_f64(jitter)
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn is_interrupted(err: &Error) -> bool {
+    matches!(err, Error::Incomplete { .. } | Error::Interrupted { .. })
+}
+
+/// Reads a `Retry-After` header given either in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, Utc::now())
+}
+
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    // a date in the past means there is no need to wait
+    Some(
+        at.with_timezone(&Utc)
+            .signed_duration_since(now)
+            .to_std()
+            .unwrap_or(Duration::from_secs(0)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::{TimeZone, Utc};
+    use reqwest::StatusCode;
+    use std::time::Duration;
+
+    use super::{is_retryable, parse_retry_after, RetryPolicy};
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable(StatusCode::FORBIDDEN));
+        assert!(!is_retryable(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+        };
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+        let capped = policy.backoff(9);
+        assert!(capped >= Duration::from_secs(5) && capped <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        };
+        assert_eq!(
+            Duration::from_secs(30),
+            policy.delay(1, Some(Duration::from_secs(30)))
+        );
+        // a server asking for hours would otherwise stall a worker
+        assert_eq!(
+            Duration::from_secs(60),
+            policy.delay(1, Some(Duration::from_secs(86400)))
+        );
+        assert!(policy.delay(1, None) <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = Utc.ymd(2015, 10, 21).and_hms(7, 28, 0);
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            parse_retry_after("120", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(0)),
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now)
+        );
+        assert_eq!(None, parse_retry_after("soon", now));
+    }
+}
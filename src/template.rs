@@ -0,0 +1,306 @@
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use urlencoding::encode;
+
+use std::path::PathBuf;
+
+use crate::{local_filename, sanitize_filename, Error, Media, Result};
+
+/// The layout litho has always used: `yyyy/mm/dd/file-name.jpg`.
+pub const DEFAULT_TEMPLATE: &str = "{year}/{month}/{day}/{filename}";
+
+/// The layout used when syncing a single album: `albums/<title>/file-name.jpg`.
+pub const ALBUM_TEMPLATE: &str = "albums/{album}/{filename}";
+
+/// What a placeholder renders as when the media has no value for it.
+const UNKNOWN: &str = "unknown";
+
+/// Where media are written when their creation time can't be parsed.
+pub const UNKNOWN_DATE_DIR: &str = "unknown-date";
+
+/// Where each media item is written, relative to the output directory, such
+/// as `{year}/{month}/{day}/{filename}`.
+///
+/// Templates use `/` to separate directories whatever the platform, and the
+/// values substituted for placeholders can never add directories of their
+/// own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTemplate {
+    template: String,
+    parts: Vec<Part>,
+    windows_safe: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+    Separator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Filename,
+    Id,
+    Mime,
+    CameraMake,
+    CameraModel,
+    Album,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Placeholder> {
+        let placeholder = match name {
+            "year" => Placeholder::Year,
+            "month" => Placeholder::Month,
+            "day" => Placeholder::Day,
+            "hour" => Placeholder::Hour,
+            "minute" => Placeholder::Minute,
+            "second" => Placeholder::Second,
+            "filename" => Placeholder::Filename,
+            "id" => Placeholder::Id,
+            "mime" => Placeholder::Mime,
+            "camera_make" => Placeholder::CameraMake,
+            "camera_model" => Placeholder::CameraModel,
+            "album" => Placeholder::Album,
+            _ => return None,
+        };
+        Some(placeholder)
+    }
+}
+
+impl Default for PathTemplate {
+    fn default() -> PathTemplate {
+        PathTemplate::parse(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<PathTemplate> {
+        let invalid = |reason: &str| Error::Template {
+            template: String::from(template),
+            reason: String::from(reason),
+        };
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(invalid("unmatched {")),
+                        }
+                    }
+                    let placeholder = Placeholder::from_name(&name)
+                        .ok_or_else(|| invalid(&format!("unknown placeholder {{{}}}", name)))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.split_off(0)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                '}' => return Err(invalid("unmatched }")),
+                '/' | '\\' => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.split_off(0)));
+                    }
+                    parts.push(Part::Separator);
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        let components = parts.split(|part| *part == Part::Separator);
+        for component in components {
+            match component {
+                [] => return Err(invalid("empty directory name")),
+                [Part::Literal(literal)] if literal == "." || literal == ".." => {
+                    return Err(invalid("directories must not be . or .."))
+                }
+                _ => {}
+            }
+        }
+        Ok(PathTemplate {
+            template: String::from(template),
+            parts,
+            windows_safe: false,
+        })
+    }
+
+    /// Also replaces the characters and names in placeholder values that
+    /// Windows doesn't allow, for backing up to an SMB share.
+    pub fn with_windows_safe_names(mut self, windows_safe: bool) -> PathTemplate {
+        self.windows_safe = windows_safe;
+        self
+    }
+
+    /// Whether this is the `yyyy/mm/dd` layout that older versions of litho
+    /// always used.
+    pub fn is_default(&self) -> bool {
+        self.template == DEFAULT_TEMPLATE
+    }
+
+    /// The path the media is written to, relative to the output directory.
+    pub fn render(&self, media: &Media, created_on: NaiveDateTime, album: Option<&str>) -> PathBuf {
+        self.render_with(media, created_on, album, |value| {
+            path_component(value, self.windows_safe)
+        })
+    }
+
+    /// The path older versions of litho wrote the media to, which URL encoded
+    /// the values of placeholders, so that `Beach Day.jpg` became
+    /// `Beach%20Day.jpg`.
+    pub(crate) fn render_legacy(
+        &self,
+        media: &Media,
+        created_on: NaiveDateTime,
+        album: Option<&str>,
+    ) -> PathBuf {
+        self.render_with(media, created_on, album, |value| match value.trim() {
+            "" | "." | ".." => String::from(UNKNOWN),
+            _ => encode(value).into_owned(),
+        })
+    }
+
+    fn render_with(
+        &self,
+        media: &Media,
+        created_on: NaiveDateTime,
+        album: Option<&str>,
+        component_for: impl Fn(&str) -> String,
+    ) -> PathBuf {
+        let (camera_make, camera_model) = media.media_metadata.camera();
+        let mut path = PathBuf::new();
+        let mut component = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => component.push_str(literal),
+                Part::Separator => path.push(component.split_off(0)),
+                Part::Placeholder(placeholder) => {
+                    let value = match placeholder {
+                        Placeholder::Year => created_on.year().to_string(),
+                        Placeholder::Month => format!("{:02}", created_on.month()),
+                        Placeholder::Day => format!("{:02}", created_on.day()),
+                        Placeholder::Hour => format!("{:02}", created_on.hour()),
+                        Placeholder::Minute => format!("{:02}", created_on.minute()),
+                        Placeholder::Second => format!("{:02}", created_on.second()),
+                        Placeholder::Filename => local_filename(media),
+                        Placeholder::Id => media.id.clone(),
+                        Placeholder::Mime => media.mime_type.clone(),
+                        Placeholder::CameraMake => camera_make.unwrap_or(UNKNOWN).to_string(),
+                        Placeholder::CameraModel => camera_model.unwrap_or(UNKNOWN).to_string(),
+                        Placeholder::Album => album.unwrap_or(UNKNOWN).to_string(),
+                    };
+                    component.push_str(&component_for(&value));
+                }
+            }
+        }
+        path.push(component);
+        path
+    }
+
+    /// The path media whose creation time can't be parsed are written to
+    /// instead, since none of the dates in the template can be filled in.
+    pub fn render_undated(&self, media: &Media) -> PathBuf {
+        let filename = path_component(&local_filename(media), self.windows_safe);
+        [UNKNOWN_DATE_DIR, &filename].iter().collect()
+    }
+}
+
+/// Makes a value safe to use as part of a single path component.
+fn path_component(value: &str, windows_safe: bool) -> String {
+    match value.trim() {
+        "" | "." | ".." => String::from(UNKNOWN),
+        _ => sanitize_filename(value, windows_safe),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+    use std::path::PathBuf;
+
+    use crate::{Media, MediaMetadata, Photo};
+
+    use super::PathTemplate;
+
+    fn media() -> Media {
+        Media {
+            id: String::from("abc123"),
+            filename: String::from("Beach Day.jpg"),
+            mime_type: String::from("image/jpeg"),
+            media_metadata: MediaMetadata {
+                creation_time: String::from("2014-10-02T15:01:23Z"),
+                photo: Some(Photo {
+                    camera_model: Some(String::from("Pixel 3")),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let created_on = NaiveDate::from_ymd(2014, 10, 2).and_hms(15, 1, 23);
+        let media = media();
+
+        let default = PathTemplate::default();
+        assert!(default.is_default());
+        assert_eq!(
+            PathBuf::from("2014/10/02/Beach Day.jpg"),
+            default.render(&media, created_on, None)
+        );
+
+        let template =
+            PathTemplate::parse("{year}/{month}/{year}-{month}-{day}_{filename}").unwrap();
+        assert!(!template.is_default());
+        assert_eq!(
+            PathBuf::from("2014/10/2014-10-02_Beach Day.jpg"),
+            template.render(&media, created_on, None)
+        );
+
+        let template = PathTemplate::parse("{album}/{camera_model}/{mime}/{id}").unwrap();
+        assert_eq!(
+            PathBuf::from("Summer/Pixel 3/image_jpeg/abc123"),
+            template.render(&media, created_on, Some("Summer"))
+        );
+        assert_eq!(
+            PathBuf::from("unknown/Pixel 3/image_jpeg/abc123"),
+            template.render(&media, created_on, None)
+        );
+    }
+
+    #[test]
+    fn test_render_undated() {
+        let template = PathTemplate::parse("{album}/{year}/{filename}").unwrap();
+        assert_eq!(
+            PathBuf::from("unknown-date/Beach Day.jpg"),
+            template.render_undated(&media())
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(PathTemplate::parse("{year}/{nope}").is_err());
+        assert!(PathTemplate::parse("{year}/{month").is_err());
+        assert!(PathTemplate::parse("year}/{filename}").is_err());
+        assert!(PathTemplate::parse("{year}//{filename}").is_err());
+        assert!(PathTemplate::parse("/{filename}").is_err());
+        assert!(PathTemplate::parse("../{filename}").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use chrono::{FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone as _, Utc};
+use chrono_tz::Tz;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The time zone creation times are converted to before they are split into
+/// dates, both for laying media out and for deciding where a sync resumes.
+///
+/// Google reports creation times in UTC, so without converting them a photo
+/// taken on an evening in the Americas would be filed under the next day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeZone {
+    /// whatever the system is set to
+    Local,
+    Utc,
+    /// a fixed offset from UTC such as `+05:30`
+    Fixed(FixedOffset),
+    /// an IANA zone such as `America/New_York`, which follows daylight saving
+    Named(Tz),
+}
+
+impl TimeZone {
+    /// The wall clock time in this zone at `utc`.
+    pub fn from_utc(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            TimeZone::Local => Local.from_utc_datetime(&utc).naive_local(),
+            TimeZone::Utc => utc,
+            TimeZone::Fixed(offset) => offset.from_utc_datetime(&utc).naive_local(),
+            TimeZone::Named(tz) => tz.from_utc_datetime(&utc).naive_local(),
+        }
+    }
+
+    /// How far this zone is ahead of UTC at `utc`, which for a named zone
+    /// depends on whether daylight saving is in effect.
+    pub fn offset(&self, utc: NaiveDateTime) -> FixedOffset {
+        match self {
+            TimeZone::Local => Local.from_utc_datetime(&utc).offset().fix(),
+            TimeZone::Utc => Utc.fix(),
+            TimeZone::Fixed(offset) => *offset,
+            TimeZone::Named(tz) => tz.from_utc_datetime(&utc).offset().fix(),
+        }
+    }
+
+    /// The current date in this zone.
+    pub fn today(&self) -> NaiveDate {
+        self.from_utc(Utc::now().naive_utc()).date()
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = String;
+
+    fn from_str(zone: &str) -> std::result::Result<TimeZone, String> {
+        match zone {
+            "local" => Ok(TimeZone::Local),
+            "utc" | "UTC" | "Z" => Ok(TimeZone::Utc),
+            _ if zone.starts_with('+') || zone.starts_with('-') => parse_offset(zone)
+                .map(TimeZone::Fixed)
+                .ok_or_else(|| format!("invalid UTC offset {}", zone)),
+            _ => zone
+                .parse()
+                .map(TimeZone::Named)
+                .map_err(|_| format!("unknown time zone {}", zone)),
+        }
+    }
+}
+
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeZone::Local => write!(f, "local"),
+            TimeZone::Utc => write!(f, "UTC"),
+            TimeZone::Fixed(offset) => write!(f, "{}", offset),
+            TimeZone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+/// Parses offsets written as `+05:30`, `+0530` or `+05`.
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    let (sign, digits) = offset.split_at(1);
+    let digits = digits.replacen(':', "", 1);
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse::<i32>().ok()?, digits[2..].parse().ok()?),
+        _ => return None,
+    };
+    if minutes >= 60 {
+        return None;
+    }
+    let seconds = (hours * 60 + minutes) * 60;
+    match sign {
+        "+" => FixedOffset::east_opt(seconds),
+        _ => FixedOffset::west_opt(seconds),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::{FixedOffset, NaiveDate};
+
+    use super::TimeZone;
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(Ok(TimeZone::Local), "local".parse());
+        assert_eq!(Ok(TimeZone::Utc), "UTC".parse());
+        assert_eq!(
+            Ok(TimeZone::Fixed(FixedOffset::east(5 * 3600 + 30 * 60))),
+            "+05:30".parse()
+        );
+        assert_eq!(
+            Ok(TimeZone::Fixed(FixedOffset::west(8 * 3600))),
+            "-0800".parse()
+        );
+        assert_eq!(
+            Ok(TimeZone::Named(chrono_tz::America::New_York)),
+            "America/New_York".parse()
+        );
+        assert!("+25:00".parse::<TimeZone>().is_err());
+        assert!("+05:75".parse::<TimeZone>().is_err());
+        assert!("Mars/Olympus_Mons".parse::<TimeZone>().is_err());
+
+        for zone in ["local", "UTC", "+05:30", "America/New_York"] {
+            assert_eq!(zone, zone.parse::<TimeZone>().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_from_utc() {
+        let utc = NaiveDate::from_ymd(2014, 10, 3).and_hms(1, 30, 0);
+        assert_eq!(utc, TimeZone::Utc.from_utc(utc));
+
+        let new_york: TimeZone = "America/New_York".parse().unwrap();
+        assert_eq!(
+            NaiveDate::from_ymd(2014, 10, 2).and_hms(21, 30, 0),
+            new_york.from_utc(utc)
+        );
+        // no daylight saving in January
+        let winter = NaiveDate::from_ymd(2015, 1, 3).and_hms(1, 30, 0);
+        assert_eq!(
+            NaiveDate::from_ymd(2015, 1, 2).and_hms(20, 30, 0),
+            new_york.from_utc(winter)
+        );
+
+        let kolkata: TimeZone = "+05:30".parse().unwrap();
+        assert_eq!(
+            NaiveDate::from_ymd(2014, 10, 3).and_hms(7, 0, 0),
+            kolkata.from_utc(utc)
+        );
+    }
+
+    #[test]
+    fn test_offset() {
+        let summer = NaiveDate::from_ymd(2014, 10, 3).and_hms(1, 30, 0);
+        let winter = NaiveDate::from_ymd(2015, 1, 3).and_hms(1, 30, 0);
+        assert_eq!("+00:00", TimeZone::Utc.offset(summer).to_string());
+
+        let new_york: TimeZone = "America/New_York".parse().unwrap();
+        assert_eq!("-04:00", new_york.offset(summer).to_string());
+        assert_eq!("-05:00", new_york.offset(winter).to_string());
+
+        let kolkata: TimeZone = "+05:30".parse().unwrap();
+        assert_eq!("+05:30", kolkata.offset(winter).to_string());
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::{Result, TokenFetcher};
+
+/// How long before it expires that an access token is replaced, so that a
+/// request is never sent with a token that runs out while in flight.
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Supplies the access tokens sent with requests to the Photos Library API.
+pub trait TokenProvider: Send {
+    /// The token to use for the next request.
+    fn access_token(&self) -> Result<String>;
+
+    /// Called when the API rejects the current token, returning a new one.
+    fn refresh(&self) -> Result<String>;
+}
+
+/// A fixed token that is never refreshed, for short lived use or tests.
+impl TokenProvider for String {
+    fn access_token(&self) -> Result<String> {
+        Ok(self.clone())
+    }
+
+    fn refresh(&self) -> Result<String> {
+        Ok(self.clone())
+    }
+}
+
+/// Lends a provider to a fetcher so that it can be used again afterwards.
+impl<T: TokenProvider + Sync + ?Sized> TokenProvider for &T {
+    fn access_token(&self) -> Result<String> {
+        (**self).access_token()
+    }
+
+    fn refresh(&self) -> Result<String> {
+        (**self).refresh()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessToken {
+    pub token: String,
+    pub expires_at: Instant,
+}
+
+/// Uses a refresh token to keep an access token current for as long as a sync
+/// runs, fetching a new one shortly before the old one expires.
+pub struct RefreshingTokenProvider<'a> {
+    token_fetcher: TokenFetcher<'a>,
+    refresh_token: String,
+    current: Mutex<Option<AccessToken>>,
+}
+
+impl<'a> RefreshingTokenProvider<'a> {
+    pub fn new(
+        token_fetcher: TokenFetcher<'a>,
+        refresh_token: String,
+    ) -> RefreshingTokenProvider<'a> {
+        RefreshingTokenProvider {
+            token_fetcher,
+            refresh_token,
+            current: Mutex::new(None),
+        }
+    }
+}
+
+impl<'a> TokenProvider for RefreshingTokenProvider<'a> {
+    fn access_token(&self) -> Result<String> {
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        match current.as_ref() {
+            Some(access) if Instant::now() + REFRESH_MARGIN < access.expires_at => {
+                Ok(access.token.clone())
+            }
+            _ => {
+                let access = self.token_fetcher.fetch_access_token(&self.refresh_token)?;
+                let token = access.token.clone();
+                *current = Some(access);
+                Ok(token)
+            }
+        }
+    }
+
+    fn refresh(&self) -> Result<String> {
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        let access = self.token_fetcher.fetch_access_token(&self.refresh_token)?;
+        let token = access.token.clone();
+        *current = Some(access);
+        Ok(token)
+    }
+}
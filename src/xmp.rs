@@ -0,0 +1,161 @@
+use std::fmt::Write;
+
+use crate::{Media, Result};
+
+/// Namespace for the properties litho adds that have no standard equivalent.
+const LITHO_NS: &str = "https://github.com/ttiimm/litho/ns/1.0/";
+
+/// Renders an XMP packet describing the media, for photo managers such as
+/// darktable and digiKam that read sidecars but not the JSON Google returns.
+pub(crate) fn to_xmp(media: &Media) -> Result<String> {
+    let metadata = &media.media_metadata;
+    let created_on = media
+        .created_on()
+        .ok()
+        .map(|created_on| created_on.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+
+    let (make, model) = metadata.camera();
+
+    let mut properties = String::new();
+    property(&mut properties, "litho:MediaId", &media.id);
+    if let Some(created_on) = &created_on {
+        property(&mut properties, "exif:DateTimeOriginal", created_on);
+        property(&mut properties, "xmp:CreateDate", created_on);
+        property(&mut properties, "photoshop:DateCreated", created_on);
+    }
+    if let Some(make) = make {
+        property(&mut properties, "tiff:Make", make);
+    }
+    if let Some(model) = model {
+        property(&mut properties, "tiff:Model", model);
+    }
+    if let Some(width) = metadata.width {
+        property(&mut properties, "exif:PixelXDimension", &width.to_string());
+    }
+    if let Some(height) = metadata.height {
+        property(&mut properties, "exif:PixelYDimension", &height.to_string());
+    }
+    if let Some(description) = media.description.as_deref().filter(|d| !d.is_empty()) {
+        let _ = write!(
+            properties,
+            "   <dc:description>\n    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    </rdf:Alt>\n   </dc:description>\n",
+            escape(description)
+        );
+    }
+
+    Ok(format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\" x:xmptk=\"litho {version}\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"
+    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"
+    xmlns:exif=\"http://ns.adobe.com/exif/1.0/\"
+    xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"
+    xmlns:tiff=\"http://ns.adobe.com/tiff/1.0/\"
+    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"
+    xmlns:litho=\"{litho_ns}\">
+{properties}  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+",
+        version = env!("CARGO_PKG_VERSION"),
+        litho_ns = LITHO_NS,
+        properties = properties,
+    ))
+}
+
+fn property(properties: &mut String, name: &str, value: &str) {
+    let _ = writeln!(properties, "   <{0}>{1}</{0}>", name, escape(value));
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{Media, MediaMetadata, Photo};
+
+    use super::{escape, to_xmp};
+
+    #[test]
+    fn test_to_xmp() {
+        let media = Media {
+            id: String::from("abc123"),
+            description: Some(String::from("Fish & chips <3")),
+            filename: String::from("test.jpg"),
+            mime_type: String::from("image/jpeg"),
+            media_metadata: MediaMetadata {
+                creation_time: String::from("2014-10-02T15:01:23.045Z"),
+                width: Some(4032),
+                height: Some(3024),
+                photo: Some(Photo {
+                    camera_make: Some(String::from("Google")),
+                    camera_model: Some(String::from("Pixel 3")),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let xmp = to_xmp(&media).unwrap();
+        assert!(xmp.starts_with("<?xpacket begin="));
+        assert!(xmp.contains("<litho:MediaId>abc123</litho:MediaId>"));
+        assert!(xmp.contains("<exif:DateTimeOriginal>2014-10-02T15:01:23Z</exif:DateTimeOriginal>"));
+        assert!(xmp.contains("<tiff:Make>Google</tiff:Make>"));
+        assert!(xmp.contains("<tiff:Model>Pixel 3</tiff:Model>"));
+        assert!(xmp.contains("<exif:PixelXDimension>4032</exif:PixelXDimension>"));
+        assert!(xmp.contains("<exif:PixelYDimension>3024</exif:PixelYDimension>"));
+        assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">Fish &amp; chips &lt;3</rdf:li>"));
+        assert!(xmp.trim_end().ends_with("<?xpacket end=\"w\"?>"));
+    }
+
+    #[test]
+    fn test_to_xmp_minimal() {
+        let media = Media {
+            id: String::from("abc123"),
+            media_metadata: MediaMetadata {
+                creation_time: String::from("2014-10-02T15:01:23Z"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let xmp = to_xmp(&media).unwrap();
+        assert!(xmp.contains("<xmp:CreateDate>2014-10-02T15:01:23Z</xmp:CreateDate>"));
+        assert!(!xmp.contains("tiff:Make>"));
+        assert!(!xmp.contains("<dc:description>"));
+    }
+
+    #[test]
+    fn test_to_xmp_unknown_date() {
+        let media = Media {
+            id: String::from("abc123"),
+            media_metadata: MediaMetadata {
+                creation_time: String::from("sometime"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let xmp = to_xmp(&media).unwrap();
+        assert!(xmp.contains("<litho:MediaId>abc123</litho:MediaId>"));
+        assert!(!xmp.contains("<xmp:CreateDate>"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a &amp; &quot;b&quot;", escape("a & \"b\""));
+    }
+}
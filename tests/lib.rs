@@ -1,14 +1,18 @@
+use chrono::NaiveDate;
 use httpmock::Method::*;
 use httpmock::{MockServer, Regex};
+use litho::TokenProvider;
 use serde_json::json;
 use tempfile::tempdir;
 
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::TcpListener;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[test]
 fn test_fetch_refresh() {
@@ -30,7 +34,7 @@ fn test_fetch_refresh() {
             .header("Content-Type", "application/json")
             .json_body(json!({"refresh_token": "yourrefreshtoken"}));
     });
-    let redirect_uri = format!("http://localhost:7878?code=mycode");
+    let redirect_uri = "http://localhost:7878?code=mycode";
     reqwest::blocking::Client::new()
         .get(redirect_uri)
         .send()
@@ -62,6 +66,157 @@ fn test_fetch_access() {
     assert_eq!("youraccesstoken", result)
 }
 
+#[test]
+fn test_fetch_access_bad_response() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/token");
+        then.status(400)
+            .header("Content-Type", "application/json")
+            .json_body(json!({"error": "invalid_grant"}));
+    });
+
+    let mock_endpoint = &server.url("/token");
+    let tf = litho::TokenFetcher::new("myclientid", "myclientsecret", mock_endpoint);
+    let result = tf.fetch_access("myrefreshtoken");
+
+    mock.assert();
+    match result {
+        Err(litho::Error::Status { status, body }) => {
+            assert_eq!(400, status.as_u16());
+            assert!(body.contains("invalid_grant"));
+        }
+        _ => panic!("expected a status error"),
+    }
+}
+
+#[test]
+fn test_fetch_access_missing_token() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/token");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({"token_type": "Bearer"}));
+    });
+
+    let mock_endpoint = &server.url("/token");
+    let tf = litho::TokenFetcher::new("myclientid", "myclientsecret", mock_endpoint);
+    let result = tf.fetch_access("myrefreshtoken");
+
+    mock.assert();
+    assert!(matches!(result, Err(litho::Error::MissingField(field)) if field == "access_token"));
+}
+
+#[test]
+fn test_refreshing_token_provider() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/token")
+            .body_contains("refresh_token=myrefreshtoken");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({"access_token": "youraccesstoken", "expires_in": 3599}));
+    });
+
+    let mock_endpoint = &server.url("/token");
+    let tf = litho::TokenFetcher::new("myclientid", "myclientsecret", mock_endpoint);
+    let tokens = litho::RefreshingTokenProvider::new(tf, String::from("myrefreshtoken"));
+
+    // the token is reused until it is close to expiring
+    assert_eq!("youraccesstoken", tokens.access_token().unwrap());
+    assert_eq!("youraccesstoken", tokens.access_token().unwrap());
+    mock.assert_hits(1);
+
+    assert_eq!("youraccesstoken", tokens.refresh().unwrap());
+    mock.assert_hits(2);
+}
+
+#[test]
+fn test_refreshing_token_provider_near_expiry() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/token");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({"access_token": "youraccesstoken", "expires_in": 60}));
+    });
+
+    let mock_endpoint = &server.url("/token");
+    let tf = litho::TokenFetcher::new("myclientid", "myclientsecret", mock_endpoint);
+    let tokens = litho::RefreshingTokenProvider::new(tf, String::from("myrefreshtoken"));
+
+    tokens.access_token().unwrap();
+    tokens.access_token().unwrap();
+    mock.assert_hits(2);
+}
+
+/// Hands out a stale token until asked to refresh.
+struct StaleTokens {
+    refreshed: AtomicBool,
+}
+
+impl litho::TokenProvider for StaleTokens {
+    fn access_token(&self) -> Result<String, litho::Error> {
+        if self.refreshed.load(Ordering::SeqCst) {
+            Ok(String::from("freshtoken"))
+        } else {
+            Ok(String::from("staletoken"))
+        }
+    }
+
+    fn refresh(&self) -> Result<String, litho::Error> {
+        self.refreshed.store(true, Ordering::SeqCst);
+        self.access_token()
+    }
+}
+
+#[test]
+fn test_fetch_media_refreshes_unauthorized() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock_stale = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/mediaItems:search")
+            .header("Authorization", "Bearer staletoken");
+        then.status(401).body("expired");
+    });
+
+    let mock_fresh = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/mediaItems:search")
+            .header("Authorization", "Bearer freshtoken");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({
+                "mediaItems": [
+                    {"id": "abc123",
+                     "baseUrl": "myurl",
+                     "filename": "foo",
+                     "mimeType": "image/jpeg",
+                     "mediaMetadata": {
+                        "creationTime": "2014-10-02T15:01:23.045123456Z"
+                    }}],
+            }));
+    });
+
+    let tokens = StaleTokens {
+        refreshed: AtomicBool::new(false),
+    };
+    let mf = litho::MediaFetcher::new(server.url(""), tokens, init_ranges());
+    let result = mf.fetch_sync(1)?;
+
+    mock_stale.assert_hits(1);
+    mock_fresh.assert_hits(1);
+    assert_eq!("abc123", result[0].id);
+    Ok(())
+}
+
 #[test]
 fn test_fetch_media() -> Result<(), Box<dyn std::error::Error>> {
     let server = MockServer::start();
@@ -114,7 +269,11 @@ fn test_fetch_media() -> Result<(), Box<dyn std::error::Error>> {
         month: 9,
         day: 22,
     };
-    let mf = litho::MediaFetcher::new(mock_endpoint, String::from("myaccesstoken"), start, end);
+    let mf = litho::MediaFetcher::new(
+        mock_endpoint,
+        String::from("myaccesstoken"),
+        vec![litho::DateRange::new(start, end)],
+    );
     let result = mf.fetch_sync(3).unwrap();
 
     mock.assert();
@@ -124,6 +283,213 @@ fn test_fetch_media() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_fetch_media_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/mediaItems:search")
+            .json_body(json!({
+            "orderBy": "MediaMetadata.creation_time",
+            "filters": {
+                "dateFilter": {
+                    "ranges": [{
+                        "startDate": {"year": 2022, "month": 9, "day": 1},
+                        "endDate": {"year": 2022, "month": 9, "day": 22}
+                    }]
+                },
+                "contentFilter": {
+                    "includedContentCategories": ["PETS"],
+                    "excludedContentCategories": ["SCREENSHOTS", "RECEIPTS"]
+                },
+                "mediaTypeFilter": {"mediaTypes": ["VIDEO"]},
+                "featureFilter": {"includedFeatures": ["FAVORITES"]},
+                "includeArchivedMedia": true
+            },
+            "pageSize": 25
+            }));
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({}));
+    });
+
+    let start = litho::YearMonthDay {
+        year: 2022,
+        month: 9,
+        day: 1,
+    };
+    let end = litho::YearMonthDay {
+        year: 2022,
+        month: 9,
+        day: 22,
+    };
+    let filters = litho::SearchFilters {
+        include_categories: vec!["pets".parse()?],
+        exclude_categories: vec!["screenshots".parse()?, "receipts".parse()?],
+        media_type: litho::MediaType::Video,
+        favorites: true,
+        include_archived: true,
+    };
+    let mf = litho::MediaFetcher::new(
+        server.url(""),
+        String::from("myaccesstoken"),
+        vec![litho::DateRange::new(start, end)],
+    )
+    .with_filters(filters);
+    let result = mf.fetch_sync(3)?;
+
+    mock.assert();
+    assert!(result.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_fetch_media_date_ranges() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/mediaItems:search")
+            .json_body(json!({
+            "orderBy": "MediaMetadata.creation_time",
+            "filters": {
+                "dateFilter": {
+                    "ranges": [{
+                        "startDate": {"year": 2019, "month": 6, "day": 1},
+                        "endDate": {"year": 2019, "month": 8, "day": 31}
+                    }, {
+                        "startDate": {"year": 2021, "month": 12, "day": 20},
+                        "endDate": {"year": 2022, "month": 1, "day": 2}
+                    }]
+                }
+            },
+            "pageSize": 25
+            }));
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({}));
+    });
+
+    let today = NaiveDate::from_ymd(2022, 9, 22);
+    let earliest = NaiveDate::from_ymd(1970, 1, 1);
+    let ranges = vec![
+        litho::DateRange::parse("2019-06-01..2019-08-31", earliest, today)?,
+        litho::DateRange::parse("2021-12-20..2022-01-02", earliest, today)?,
+    ];
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), ranges);
+    let result = mf.fetch_sync(3)?;
+
+    mock.assert();
+    assert!(result.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_fetch_media_whole_library() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    // no date filter, so that media dated before 1970 are found too
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/mediaItems:search")
+            .json_body(json!({
+            "filters": {
+                "featureFilter": {"includedFeatures": ["FAVORITES"]}
+            },
+            "pageSize": 25
+            }));
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({}));
+    });
+
+    let filters = litho::SearchFilters {
+        favorites: true,
+        ..Default::default()
+    };
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), Vec::new())
+        .with_filters(filters);
+    let result = mf.fetch_sync(3)?;
+
+    mock.assert();
+    assert!(result.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_fetch_media_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/mediaItems:search");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({
+                "mediaItems": [
+                    {"id": "abc123",
+                     "description": "at the lake",
+                     "productUrl": "https://photos.google.com/lr/photo/abc123",
+                     "baseUrl": "myurl",
+                     "filename": "foo.jpg",
+                     "mimeType": "image/jpeg",
+                     "mediaMetadata": {
+                        "creationTime": "2014-10-02T15:01:23.045123456Z",
+                        "width": "4032",
+                        "height": "3024",
+                        "photo": {
+                            "cameraMake": "Google",
+                            "cameraModel": "Pixel 3",
+                            "focalLength": 4.44,
+                            "apertureFNumber": 1.8,
+                            "isoEquivalent": 60,
+                            "exposureTime": "0.008s"
+                        }
+                     },
+                     "contributorInfo": {
+                        "profilePictureBaseUrl": "pictureurl",
+                        "displayName": "Tim"
+                     }},
+                    {"id": "def456",
+                     "baseUrl": "myurl",
+                     "filename": "bar.mp4",
+                     "mimeType": "video/mp4",
+                     "mediaMetadata": {
+                        "creationTime": "2014-10-03T15:01:23Z",
+                        "width": "1920",
+                        "height": "1080",
+                        "video": {
+                            "fps": 29.97,
+                            "status": "READY"
+                        }
+                     }}],
+            }));
+    });
+
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), init_ranges());
+    let result = mf.fetch_sync(2).unwrap();
+
+    mock.assert();
+    let photo = &result[0];
+    assert_eq!(Some("at the lake"), photo.description.as_deref());
+    assert_eq!(Some(4032), photo.media_metadata.width);
+    assert_eq!(Some(3024), photo.media_metadata.height);
+    let camera = photo.media_metadata.photo.as_ref().unwrap();
+    assert_eq!(Some("Pixel 3"), camera.camera_model.as_deref());
+    assert_eq!(Some(60), camera.iso_equivalent);
+    assert_eq!(Some("0.008s"), camera.exposure_time.as_deref());
+    let contributor = photo.contributor_info.as_ref().unwrap();
+    assert_eq!(Some("Tim"), contributor.display_name.as_deref());
+    assert_eq!("myurl=w4032-h3024-d", photo.download_url());
+
+    let video = &result[1];
+    let info = video.media_metadata.video.as_ref().unwrap();
+    assert_eq!(Some(29.97), info.fps);
+    assert_eq!(Some(litho::VideoProcessingStatus::Ready), info.status);
+    assert_eq!("myurl=dv", video.download_url());
+    Ok(())
+}
+
 #[test]
 fn test_fetch_media_no_mediaitems() -> Result<(), Box<dyn std::error::Error>> {
     let server = MockServer::start();
@@ -167,7 +533,11 @@ fn test_fetch_media_no_mediaitems() -> Result<(), Box<dyn std::error::Error>> {
         month: 9,
         day: 22,
     };
-    let mf = litho::MediaFetcher::new(mock_endpoint, String::from("myaccesstoken"), start, end);
+    let mf = litho::MediaFetcher::new(
+        mock_endpoint,
+        String::from("myaccesstoken"),
+        vec![litho::DateRange::new(start, end)],
+    );
     let result = mf.fetch_sync(3).unwrap();
 
     mock.assert();
@@ -176,7 +546,6 @@ fn test_fetch_media_no_mediaitems() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[test]
-#[should_panic]
 fn test_fetch_media_bad_response() {
     let server = MockServer::start();
 
@@ -222,9 +591,63 @@ fn test_fetch_media_bad_response() {
         month: 9,
         day: 22,
     };
-    let mf = litho::MediaFetcher::new(mock_endpoint, String::from("myaccesstoken"), start, end);
-    mf.fetch_sync(3).unwrap();
+    let mf = litho::MediaFetcher::new(
+        mock_endpoint,
+        String::from("myaccesstoken"),
+        vec![litho::DateRange::new(start, end)],
+    );
+    let result = mf.fetch_sync(3);
+
     mock.assert();
+    match result {
+        Err(litho::Error::Status { status, body }) => {
+            assert_eq!(404, status.as_u16());
+            assert!(body.contains("Not found"));
+        }
+        _ => panic!("expected a status error"),
+    }
+}
+
+#[test]
+fn test_fetch_media_retries() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/mediaItems:search");
+        then.status(429)
+            .header("Retry-After", "0")
+            .body("slow down");
+    });
+
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), init_ranges())
+        .with_retry_policy(init_retry_policy());
+    let result = mf.fetch_sync(3);
+
+    mock.assert_hits(3);
+    match result {
+        Err(litho::Error::Status { status, .. }) => assert_eq!(429, status.as_u16()),
+        _ => panic!("expected a status error"),
+    }
+}
+
+#[test]
+fn test_fetch_media_no_retry_when_permanent() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/v1/mediaItems:search");
+        then.status(403).body("forbidden");
+    });
+
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), init_ranges())
+        .with_retry_policy(init_retry_policy());
+    let result = mf.fetch_sync(3);
+
+    mock.assert_hits(1);
+    match result {
+        Err(litho::Error::Status { status, .. }) => assert_eq!(403, status.as_u16()),
+        _ => panic!("expected a status error"),
+    }
 }
 
 #[test]
@@ -325,7 +748,11 @@ fn test_fetch_media_pagination() -> Result<(), Box<dyn std::error::Error>> {
         month: 9,
         day: 22,
     };
-    let mf = litho::MediaFetcher::new(mock_endpoint, String::from("myaccesstoken"), start, end);
+    let mf = litho::MediaFetcher::new(
+        mock_endpoint,
+        String::from("myaccesstoken"),
+        vec![litho::DateRange::new(start, end)],
+    );
     let result = mf.fetch_sync(3).unwrap();
 
     mock_first.assert();
@@ -345,42 +772,860 @@ fn test_fetch_media_pagination() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[test]
-fn test_write_media() -> Result<(), Box<dyn std::error::Error>> {
+fn test_fetch_albums() -> Result<(), Box<dyn std::error::Error>> {
     let server = MockServer::start();
-    let binary_content = b"\xca\xfe\xba\xbe";
 
-    let mock = server.mock(|when, then| {
+    let mock_first = server.mock(|when, then| {
         when.method(GET)
-            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
-        then.status(200).body(binary_content);
+            .path("/v1/albums")
+            .query_param("pageSize", "50")
+            .header("Authorization", "Bearer myaccesstoken")
+            .matches(|req| {
+                !req.query_params
+                    .as_ref()
+                    .unwrap()
+                    .iter()
+                    .any(|(k, _)| k.eq("pageToken"))
+            });
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({
+            "albums": [
+                {"id": "album1",
+                 "title": "Camping",
+                 "productUrl": "https://photos.google.com/lr/album/album1",
+                 "mediaItemsCount": "2"}],
+            "nextPageToken": "the_next_page"
+            }));
+    });
+    let mock_last = server.mock(|when, then| {
+        when.method(GET)
+            .path("/v1/albums")
+            .query_param("pageToken", "the_next_page");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({
+            "albums": [{"id": "album2"}]
+            }));
     });
 
-    let temp_dir = tempdir()?;
-    let temp_path_buf = PathBuf::from(temp_dir.path());
-    let media_writer = litho::MediaWriter::new(&temp_path_buf);
-    let media = init_media(&server, None);
-    let result = media_writer.write_media(media, 2);
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), init_ranges());
+    let albums = mf.fetch_albums()?;
 
-    mock.assert_hits(2);
-    assert_eq!(8, result.unwrap());
+    mock_first.assert();
+    mock_last.assert();
+    assert_eq!(2, albums.len());
+    assert_eq!("Camping", albums[0].title);
+    assert_eq!(Some(2), albums[0].media_items_count);
+    assert_eq!("album2", albums[1].id);
+    assert_eq!("", albums[1].title);
+    Ok(())
+}
 
-    let mut path_buf_test = temp_path_buf.clone();
-    path_buf_test.push("2014/10/02/test.jpg");
-    // println!("path={:?}", path_buf_test.as_path());
-    assert_write_media(&path_buf_test, &binary_content);
+#[test]
+fn test_fetch_shared_albums() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path("/v1/sharedAlbums")
+            .header("Authorization", "Bearer myaccesstoken");
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({
+            "sharedAlbums": [
+                {"id": "shared1",
+                 "title": "Grandkids",
+                 "mediaItemsCount": "120",
+                 "shareInfo": {
+                    "shareableUrl": "https://photos.app.goo.gl/shared1",
+                    "isJoined": true
+                 }}]
+            }));
+    });
+
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), init_ranges());
+    let albums = mf.fetch_shared_albums()?;
+
+    mock.assert();
+    assert_eq!(1, albums.len());
+    assert_eq!("Grandkids", albums[0].title);
+    let share_info = albums[0].share_info.as_ref().unwrap();
+    assert!(share_info.is_joined);
+    assert!(!share_info.is_owned);
+    Ok(())
+}
+
+#[test]
+fn test_fetch_media_album() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/mediaItems:search")
+            .json_body(json!({
+            "albumId": "album1",
+            "pageSize": 25
+            }));
+        then.status(200)
+            .header("Content-Type", "application/json")
+            .json_body(json!({
+                "mediaItems": [
+                    {"id": "abc123",
+                     "baseUrl": "myurl",
+                     "filename": "foo",
+                     "mimeType": "image/jpeg",
+                     "mediaMetadata": {
+                        "creationTime": "2014-10-02T15:01:23.045123456Z"
+                    }}],
+            }));
+    });
+
+    let mf = litho::MediaFetcher::new(server.url(""), String::from("myaccesstoken"), init_ranges())
+        .with_album("album1");
+    let result = mf.fetch_sync(3)?;
+
+    mock.assert();
+    assert_eq!("abc123", result[0].id);
+    Ok(())
+}
+
+#[test]
+fn test_write_media() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let media = init_media(&server, None);
+    let result = media_writer.write_media(media, 2);
+
+    mock.assert_hits(2);
+    assert_eq!(8, result.unwrap());
+
+    let mut path_buf_test = temp_path_buf.clone();
+    path_buf_test.push("2014/10/02/test.jpg");
+    // println!("path={:?}", path_buf_test.as_path());
+    assert_write_media(&path_buf_test, binary_content);
+
+    let mut path_buf_camping = temp_path_buf.clone();
+    path_buf_camping.push("2014/10/03/camping.jpg");
+    assert_write_media(&path_buf_camping, binary_content);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_bad_filename() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let media = init_media(&server, Some("0/1.jpg"));
+    let result = media_writer.write_media(media, 2);
+
+    mock.assert_hits(2);
+    assert_eq!(8, result.unwrap());
+
+    let mut path_buf_test = temp_path_buf.clone();
+    path_buf_test.push("2014/10/02/0_1.jpg");
+    // println!("path={:?}", path_buf_test.as_path());
+    assert_write_media(&path_buf_test, binary_content);
+
+    let mut path_buf_camping = temp_path_buf.clone();
+    path_buf_camping.push("2014/10/03/camping.jpg");
+    assert_write_media(&path_buf_camping, binary_content);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_bad_creation_time() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let mut media = init_media(&server, None);
+    media[0].media_metadata.creation_time = String::from("not a date");
+    media[1].media_metadata.creation_time = String::from("2014-10-03T17:01:23.456+02:00");
+    let result = media_writer.write_media(media, 2);
+
+    mock.assert_hits(2);
+    assert_eq!(8, result.unwrap());
+
+    let mut undated = temp_path_buf.clone();
+    undated.push("unknown-date/test.jpg");
+    assert_write_media(&undated, binary_content);
+
+    let mut offset = temp_path_buf.clone();
+    offset.push("2014/10/03/camping.jpg");
+    assert_write_media(&offset, binary_content);
+    assert_eq!(
+        UNIX_EPOCH + Duration::from_millis(1412348483456),
+        fs::metadata(&offset)?.modified()?
+    );
+    Ok(())
+}
+
+#[test]
+fn test_write_media_when_album_has_more() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let media = init_media(&server, None);
+    let result = media_writer.write_media(media, 1);
+
+    mock.assert();
+    assert_eq!(4, result.unwrap());
+
+    let mut path_buf_test = temp_path_buf.clone();
+    path_buf_test.push("2014/10/02/test.jpg");
+    // println!("path={:?}", path_buf_test.as_path());
+    assert_write_media(&path_buf_test, binary_content);
+
+    let mut path_buf_camping = temp_path_buf.clone();
+    path_buf_camping.push("2014/10/03/camping.jpg");
+    assert!(!path_buf_camping.as_path().exists());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_records_index() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let result = media_writer.write_media(init_media(&server, None), 2);
+    assert_eq!(8, result.unwrap());
+
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(2, index.len());
+    let entry = index.get("abc123").unwrap();
+    assert_eq!(litho::DownloadStatus::Downloaded, entry.status);
+    assert_eq!(PathBuf::from("2014/10/02/test.jpg"), entry.path);
+    assert_eq!(4, entry.size);
+    assert_eq!(
+        "65ab12a8ff3263fbc257e5ddf0aa563c64573d0bab1f1115b9b107834cfa6971",
+        entry.checksum
+    );
+
+    // the same ids are skipped even when the file has a different name
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let result = media_writer.write_media(init_media(&server, Some("renamed.jpg")), 2);
+    assert_eq!(0, result.unwrap());
+    mock.assert_hits(2);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_original_size() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/123=w4032-h3024-d");
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let mut media = init_media(&server, None);
+    media.truncate(1);
+    media[0].media_metadata.width = Some(4032);
+    media[0].media_metadata.height = Some(3024);
+    let result = media_writer.write_media(media, 1);
+    assert_eq!(4, result.unwrap());
+    mock.assert();
+    Ok(())
+}
+
+#[test]
+fn test_write_media_video() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let still = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/789=d");
+        then.status(200).body(b"\xde\xad\xbe\xef");
+    });
+    let video = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/789=dv");
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let result = media_writer.write_media(vec![init_video(&server, "READY")], 1);
+    assert_eq!(4, result.unwrap());
+    still.assert_hits(0);
+    video.assert_hits(1);
+
+    // the name says jpg but the bytes are an mp4
+    let mut expected = temp_path_buf.clone();
+    expected.push("2014/10/01/clip.mp4");
+    assert_write_media(&expected, binary_content);
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    let entry = index.get("ghi789").unwrap();
+    assert_eq!("clip.jpg", entry.filename);
+    assert_eq!(PathBuf::from("2014/10/01/clip.mp4"), entry.path);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_video_processing() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let mut media = init_media(&server, None);
+    media.push(init_video(&server, "PROCESSING"));
+    let result = media_writer.write_media(media, 3);
+    assert_eq!(8, result.unwrap());
+    mock.assert_hits(2);
+
+    let mut expected = temp_path_buf.clone();
+    expected.push("2014/10/01/clip.mp4");
+    assert!(!expected.exists());
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    let entry = index.get("ghi789").unwrap();
+    assert_eq!(litho::DownloadStatus::Processing, entry.status);
+    // the next sync resumes from the video rather than the newest photo
+    let expected = litho::YearMonthDay {
+        year: 2014,
+        month: 10,
+        day: 1,
+    };
+    assert_eq!(Some(expected), media_writer.most_recent_date());
+
+    // once it is ready it is downloaded like any other item
+    let result = media_writer.write_media(vec![init_video(&server, "READY")], 1);
+    assert_eq!(4, result.unwrap());
+    mock.assert_hits(3);
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    let entry = index.get("ghi789").unwrap();
+    assert_eq!(litho::DownloadStatus::Downloaded, entry.status);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_video_failed() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let mut media = init_media(&server, None);
+    media.push(init_video(&server, "FAILED"));
+    let result = media_writer.write_media(media, 3);
+    assert_eq!(8, result.unwrap());
+    mock.assert_hits(2);
+
+    assert!(!temp_path_buf.join("2014/10/01/clip.mp4").exists());
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    let entry = index.get("ghi789").unwrap();
+    assert_eq!(litho::DownloadStatus::Failed, entry.status);
+    // it will never be ready, so the next sync resumes from the newest photo
+    let expected = litho::YearMonthDay {
+        year: 2014,
+        month: 10,
+        day: 3,
+    };
+    assert_eq!(Some(expected), media_writer.most_recent_date());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_json_sidecar() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_json_sidecar(true);
+    let mut media = init_media(&server, None);
+    media[0].description = Some(String::from("at the lake"));
+    let result = media_writer.write_media(media, 2);
+    assert_eq!(8, result.unwrap());
+
+    let mut sidecar = temp_path_buf.clone();
+    sidecar.push("2014/10/02/test.jpg.json");
+    let written: litho::Media = serde_json::from_str(&fs::read_to_string(&sidecar)?)?;
+    assert_eq!("abc123", written.id);
+    assert_eq!(Some("at the lake"), written.description.as_deref());
+    assert_eq!(
+        "2014-10-02T15:01:23.045123456Z",
+        written.media_metadata.creation_time
+    );
+
+    // a missing sidecar is written again without downloading the media
+    fs::remove_file(&sidecar)?;
+    let result = media_writer.write_media(init_media(&server, None), 2);
+    assert_eq!(0, result.unwrap());
+    assert!(sidecar.exists());
+    mock.assert_hits(2);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_xmp_sidecar() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_xmp_sidecar(true);
+    let result = media_writer.write_media(init_media(&server, None), 2);
+    assert_eq!(8, result.unwrap());
+
+    let mut sidecar = temp_path_buf.clone();
+    sidecar.push("2014/10/02/test.jpg.xmp");
+    let xmp = fs::read_to_string(&sidecar)?;
+    assert!(xmp.contains("<litho:MediaId>abc123</litho:MediaId>"));
+    assert!(xmp.contains("<exif:DateTimeOriginal>2014-10-02T15:01:23Z</exif:DateTimeOriginal>"));
+    let mut json = temp_path_buf.clone();
+    json.push("2014/10/02/test.jpg.json");
+    assert!(!json.exists());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_embedded_exif() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let jpeg = b"\xff\xd8\xff\xda\0\x04\x01\x02\xca\xfe\xba\xbe\xff\xd9";
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(jpeg);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?
+        .with_embedded_exif(true)
+        .with_timezone("+02:00".parse()?);
+    let mut media = init_media(&server, None);
+    media[0].description = Some(String::from("at the lake"));
+    media.truncate(1);
+    media_writer.write_media(media, 1).unwrap();
+
+    let mut path = temp_path_buf.clone();
+    path.push("2014/10/02/test.jpg");
+    let written = fs::read(&path)?;
+    assert_eq!(b"\xff\xd8\xff\xe1", &written[..4]);
+    assert!(written.ends_with(&jpeg[2..]));
+    let contains = |needle: &[u8]| written.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"Exif\0\0"));
+    // written as the time on the clock where it was taken
+    assert!(contains(b"2014:10:02 17:01:23\0"));
+    assert!(contains(b"+02:00\0"));
+    assert!(contains(b"at the lake\0"));
+
+    // the index describes the file as it is after the rewrite
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(written.len() as u64, index.get("abc123").unwrap().size);
+    assert!(index.verify(&temp_path_buf)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_sets_times() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    media_writer.write_media(init_media(&server, None), 2)?;
+
+    let mut path = temp_path_buf.clone();
+    path.push("2014/10/02/test.jpg");
+    // 2014-10-02T15:01:23.045123456Z
+    let created = UNIX_EPOCH + Duration::new(1412262083, 45123456);
+    let metadata = fs::metadata(&path)?;
+    assert_eq!(created, metadata.modified()?);
+    assert_eq!(created, metadata.accessed()?);
+    Ok(())
+}
+
+#[test]
+fn test_fix_times() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    media_writer.write_media(init_media(&server, None), 2)?;
+
+    // touched since it was downloaded
+    let mut indexed = temp_path_buf.clone();
+    indexed.push("2014/10/02/test.jpg");
+    File::options()
+        .write(true)
+        .open(&indexed)?
+        .set_modified(SystemTime::now())?;
+
+    // downloaded before there was an index, but with a sidecar
+    let mut unindexed = temp_path_buf.clone();
+    unindexed.push("2014/10/05");
+    fs::create_dir_all(&unindexed)?;
+    unindexed.push("old.jpg");
+    fs::write(&unindexed, b"\xde\xad\xbe\xef")?;
+    let mut media = init_media(&server, Some("old.jpg"));
+    media[0].id = String::from("xyz789");
+    media[0].media_metadata.creation_time = String::from("2014-10-05T10:00:00Z");
+    fs::write(
+        unindexed.with_file_name("old.jpg.json"),
+        serde_json::to_string(&media[0])?,
+    )?;
+
+    // a Takeout sidecar, which isn't one of litho's
+    let foreign = unindexed.with_file_name("takeout.jpg");
+    fs::write(&foreign, b"\xde\xad\xbe\xef")?;
+    fs::write(
+        foreign.with_file_name("takeout.jpg.json"),
+        r#"{"title": "takeout.jpg", "photoTakenTime": {"timestamp": "1412503200"}}"#,
+    )?;
+    let foreign_modified = fs::metadata(&foreign)?.modified()?;
+
+    assert_eq!(3, litho::fix_times(&temp_path_buf)?);
+    assert_eq!(foreign_modified, fs::metadata(&foreign)?.modified()?);
+    assert_eq!(
+        UNIX_EPOCH + Duration::new(1412262083, 45123456),
+        fs::metadata(&indexed)?.modified()?
+    );
+    assert_eq!(
+        UNIX_EPOCH + Duration::from_secs(1412503200),
+        fs::metadata(&unindexed)?.modified()?
+    );
+    Ok(())
+}
+
+#[test]
+fn test_migrate_names() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_json_sidecar(true);
+    media_writer.write_media(init_media(&server, Some("Beach Day (1).jpg")), 2)?;
+
+    let mut day_dir = temp_path_buf.clone();
+    day_dir.push("2014/10/02");
+    let readable = day_dir.join("Beach Day (1).jpg");
+    assert!(readable.exists());
+
+    // lay it out the way earlier versions did
+    let encoded = day_dir.join("Beach%20Day%20%281%29.jpg");
+    fs::rename(&readable, &encoded)?;
+    fs::rename(
+        day_dir.join("Beach Day (1).jpg.json"),
+        day_dir.join("Beach%20Day%20%281%29.jpg.json"),
+    )?;
+    let mut index = litho::SyncIndex::open(&temp_path_buf)?;
+    let entry = index.get("abc123").unwrap().clone();
+    index.record(litho::IndexEntry {
+        path: PathBuf::from("2014/10/02/Beach%20Day%20%281%29.jpg"),
+        ..entry
+    })?;
+    // not something litho would have written
+    fs::write(day_dir.join("100%.jpg"), b"\xde\xad\xbe\xef")?;
+
+    let renamed = litho::migrate_names(&temp_path_buf, false)?;
+    assert_eq!(
+        vec![(
+            PathBuf::from("2014/10/02/Beach%20Day%20%281%29.jpg"),
+            PathBuf::from("2014/10/02/Beach Day (1).jpg")
+        )],
+        renamed
+    );
+    assert!(readable.exists());
+    assert!(!encoded.exists());
+    assert!(day_dir.join("Beach Day (1).jpg.json").exists());
+    assert!(day_dir.join("100%.jpg").exists());
+
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(
+        PathBuf::from("2014/10/02/Beach Day (1).jpg"),
+        index.get("abc123").unwrap().path
+    );
+    assert!(litho::migrate_names(&temp_path_buf, false)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_adopts_legacy_paths() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    // written by earlier versions, with URL encoded names and UTC dates
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let encoded = temp_path_buf.join("2014/10/02/Beach%20Day.jpg");
+    let utc = temp_path_buf.join("2014/10/03/camping.jpg");
+    for legacy in [&encoded, &utc] {
+        fs::create_dir_all(legacy.parent().unwrap())?;
+        fs::write(legacy, b"\xde\xad\xbe\xef")?;
+    }
+
+    // both were taken in the early hours of the next day at +10:00
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_timezone("+10:00".parse()?);
+    let result = media_writer.write_media(init_media(&server, Some("Beach Day.jpg")), 2);
+    assert_eq!(0, result.unwrap());
+    mock.assert_hits(0);
+    assert!(!temp_path_buf.join("2014/10/03/Beach Day.jpg").exists());
+    assert!(!temp_path_buf.join("2014/10/04/camping.jpg").exists());
+
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(
+        PathBuf::from("2014/10/02/Beach%20Day.jpg"),
+        index.get("abc123").unwrap().path
+    );
+    assert_eq!(
+        PathBuf::from("2014/10/03/camping.jpg"),
+        index.get("def456").unwrap().path
+    );
+    assert!(index.verify(&temp_path_buf)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_path_template() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let template = litho::PathTemplate::parse("{year}/{album}/{year}-{month}-{day}_{filename}")?;
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?
+        .with_path_template(template)
+        .with_album(Some(litho::AlbumInfo {
+            id: String::from("album1"),
+            title: String::from("Camping"),
+            ..Default::default()
+        }));
+    let result = media_writer.write_media(init_media(&server, None), 2);
+    assert_eq!(8, result.unwrap());
+
+    let mut expected = temp_path_buf.clone();
+    expected.push("2014/Camping/2014-10-03_camping.jpg");
+    assert_write_media(&expected, binary_content);
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(
+        PathBuf::from("2014/Camping/2014-10-02_test.jpg"),
+        index.get("abc123").unwrap().path
+    );
+    Ok(())
+}
+
+#[test]
+fn test_write_media_album() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let album = litho::AlbumInfo {
+        id: String::from("album1"),
+        title: String::from("Camping"),
+        ..Default::default()
+    };
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?
+        .with_path_template(litho::PathTemplate::parse(litho::ALBUM_TEMPLATE)?)
+        .with_album(Some(album));
+    assert_eq!(8, media_writer.write_media(init_media(&server, None), 2)?);
+
+    let mut expected = temp_path_buf.clone();
+    expected.push("albums/Camping/test.jpg");
+    assert_write_media(&expected, binary_content);
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    let entry = index.get("abc123").unwrap();
+    assert_eq!(Some(String::from("album1")), entry.album_id);
+    // an album says nothing about where the library sync got to
+    assert_eq!(None, media_writer.most_recent_date());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_contributor() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let album = litho::AlbumInfo {
+        id: String::from("shared1"),
+        title: String::from("Grandkids"),
+        share_info: Some(litho::ShareInfo::default()),
+        ..Default::default()
+    };
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_album(Some(album));
+    let mut media = init_media(&server, None);
+    media[0].contributor_info = Some(litho::ContributorInfo {
+        profile_picture_base_url: None,
+        display_name: Some(String::from("Grandma")),
+    });
+    media_writer.write_media(media, 2)?;
 
-    let mut path_buf_camping = temp_path_buf.clone();
-    path_buf_camping.push("2014/10/03/camping.jpg");
-    assert_write_media(&path_buf_camping, &binary_content);
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    let entry = index.get("abc123").unwrap();
+    assert_eq!(Some(String::from("Grandma")), entry.contributor);
+    assert_eq!(Some(String::from("shared1")), entry.album_id);
+    assert_eq!(None, index.get("def456").unwrap().contributor);
     Ok(())
 }
 
 #[test]
-fn test_write_media_bad_filename() -> Result<(), Box<dyn std::error::Error>> {
+fn test_mirror_albums() -> Result<(), Box<dyn std::error::Error>> {
     let server = MockServer::start();
     let binary_content = b"\xca\xfe\xba\xbe";
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
 
-    let mock = server.mock(|when, then| {
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    media_writer.write_media(init_media(&server, None), 2)?;
+
+    let album = |id: &str, title: &str| litho::AlbumInfo {
+        id: String::from(id),
+        title: String::from(title),
+        ..Default::default()
+    };
+    let ids = |ids: &[&str]| ids.iter().map(|id| String::from(*id)).collect::<Vec<_>>();
+    let album_mirror = litho::AlbumMirror::new(&temp_path_buf, litho::LinkKind::Symlink);
+    // a file of the user's own that litho leaves alone
+    fs::create_dir_all(temp_path_buf.join("albums/Beach"))?;
+    fs::write(temp_path_buf.join("albums/Beach/notes.txt"), b"sandy")?;
+
+    let changes = album_mirror.mirror(&[
+        (album("album1", "Camping"), ids(&["abc123", "def456"])),
+        (album("album2", "Beach"), ids(&["abc123", "missing"])),
+    ])?;
+    assert_eq!(3, changes.added);
+    assert_eq!(0, changes.removed);
+    let link = temp_path_buf.join("albums/Camping/test.jpg");
+    assert_eq!(
+        PathBuf::from("../../2014/10/02/test.jpg"),
+        fs::read_link(&link)?
+    );
+    assert_write_media(&link, binary_content);
+    assert!(temp_path_buf.join("albums/Camping/camping.jpg").exists());
+    assert!(temp_path_buf.join("albums/Beach/test.jpg").exists());
+
+    // test.jpg left Camping and the Beach album was deleted
+    let changes = album_mirror.mirror(&[(album("album1", "Camping"), ids(&["def456"]))])?;
+    assert_eq!(0, changes.added);
+    assert_eq!(2, changes.removed);
+    assert!(fs::symlink_metadata(&link).is_err());
+    assert!(temp_path_buf.join("albums/Camping/camping.jpg").exists());
+    assert!(!temp_path_buf.join("albums/Beach/test.jpg").exists());
+    assert!(temp_path_buf.join("albums/Beach/notes.txt").exists());
+    // the media themselves are untouched
+    assert_write_media(&temp_path_buf.join("2014/10/02/test.jpg"), binary_content);
+    Ok(())
+}
+
+#[test]
+fn test_mirror_albums_hardlink() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+    let _mock = server.mock(|when, then| {
         when.method(GET)
             .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
         then.status(200).body(binary_content);
@@ -388,26 +1633,354 @@ fn test_write_media_bad_filename() -> Result<(), Box<dyn std::error::Error>> {
 
     let temp_dir = tempdir()?;
     let temp_path_buf = PathBuf::from(temp_dir.path());
-    let media_writer = litho::MediaWriter::new(&temp_path_buf);
-    let media = init_media(&server, Some("0/1.jpg"));
-    let result = media_writer.write_media(media, 2);
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    media_writer.write_media(init_media(&server, None), 2)?;
 
-    mock.assert_hits(2);
+    let album = litho::AlbumInfo {
+        id: String::from("album1"),
+        title: String::from("Camping"),
+        ..Default::default()
+    };
+    let album_mirror = litho::AlbumMirror::new(&temp_path_buf, litho::LinkKind::Hardlink);
+    let memberships = [(album, vec![String::from("def456")])];
+    assert_eq!(1, album_mirror.mirror(&memberships)?.added);
+
+    let link = temp_path_buf.join("albums/Camping/camping.jpg");
+    assert!(!fs::symlink_metadata(&link)?.file_type().is_symlink());
+    assert_write_media(&link, binary_content);
+    // nothing changes when run again
+    assert_eq!(
+        litho::MirrorChanges::default(),
+        album_mirror.mirror(&memberships)?
+    );
+    Ok(())
+}
+
+#[test]
+fn test_migrate_names_hardlink_mirror() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    // adopted where an earlier version wrote it, under its encoded name
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let encoded = temp_path_buf.join("2014/10/02/Beach%20Day.jpg");
+    fs::create_dir_all(encoded.parent().unwrap())?;
+    fs::write(&encoded, b"\xde\xad\xbe\xef")?;
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    media_writer.write_media(init_media(&server, Some("Beach Day.jpg")), 2)?;
+
+    let album = litho::AlbumInfo {
+        id: String::from("album1"),
+        title: String::from("Beach"),
+        ..Default::default()
+    };
+    let album_mirror = litho::AlbumMirror::new(&temp_path_buf, litho::LinkKind::Hardlink);
+    let memberships = [(album, vec![String::from("abc123")])];
+    assert_eq!(1, album_mirror.mirror(&memberships)?.added);
+    let link = temp_path_buf.join("albums/Beach/Beach%20Day.jpg");
+    assert!(link.exists());
+
+    // only the media is renamed, the mirror's link is left to the mirror
+    let renamed = litho::migrate_names(&temp_path_buf, false)?;
+    assert_eq!(
+        vec![(
+            PathBuf::from("2014/10/02/Beach%20Day.jpg"),
+            PathBuf::from("2014/10/02/Beach Day.jpg")
+        )],
+        renamed
+    );
+    assert!(link.exists());
+    assert!(!temp_path_buf.join("albums/Beach/Beach Day.jpg").exists());
+
+    // which replaces it with one named after the media's new name
+    let changes = album_mirror.mirror(&memberships)?;
+    assert_eq!(
+        litho::MirrorChanges {
+            added: 1,
+            removed: 1
+        },
+        changes
+    );
+    assert!(!link.exists());
+    assert_write_media(
+        &temp_path_buf.join("albums/Beach/Beach Day.jpg"),
+        b"\xde\xad\xbe\xef",
+    );
+    Ok(())
+}
+
+#[test]
+fn test_profiles() -> Result<(), Box<dyn std::error::Error>> {
+    let config_dir = tempdir()?;
+    let path = config_dir.path().join("litho").join("profiles.json");
+
+    let mut profiles = litho::Profiles::open(&path)?;
+    assert_eq!(0, profiles.profiles().count());
+    let alice = litho::Profile {
+        name: String::from("alice"),
+        output: PathBuf::from("/backups/alice"),
+    };
+    profiles.add(alice.clone())?;
+    profiles.add(litho::Profile {
+        name: String::from("bob"),
+        output: PathBuf::from("/backups/bob"),
+    })?;
+
+    // names end up in the keyring entry and outputs hold each index
+    let invalid = |name: &str, output: &str| litho::Profile {
+        name: String::from(name),
+        output: PathBuf::from(output),
+    };
+    assert!(profiles.add(invalid("alice", "/backups/other")).is_err());
+    assert!(profiles
+        .add(invalid("carol:work", "/backups/carol"))
+        .is_err());
+    assert!(profiles.add(invalid("", "/backups/carol")).is_err());
+    assert!(profiles.add(invalid("carol", "/backups/bob")).is_err());
+
+    let mut profiles = litho::Profiles::open(&path)?;
+    assert_eq!(Some(&alice), profiles.get("alice"));
+    let names: Vec<&str> = profiles
+        .profiles()
+        .map(|profile| profile.name.as_str())
+        .collect();
+    assert_eq!(vec!["alice", "bob"], names);
+
+    assert_eq!(Some(alice), profiles.remove("alice")?);
+    assert_eq!(None, profiles.remove("alice")?);
+    let profiles = litho::Profiles::open(&path)?;
+    assert_eq!(None, profiles.get("alice"));
+    assert!(profiles.get("bob").is_some());
+    Ok(())
+}
+
+#[test]
+fn test_most_recent_date_layout() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    fs::create_dir_all(temp_path_buf.join("2014/10/02"))?;
+
+    // without an index, the date directories are only trusted in the default layout
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let expected = litho::YearMonthDay {
+        year: 2014,
+        month: 10,
+        day: 2,
+    };
+    assert_eq!(Some(expected), media_writer.most_recent_date());
+    let template = litho::PathTemplate::parse("{camera_model}/{filename}")?;
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_path_template(template);
+    assert_eq!(None, media_writer.most_recent_date());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_timezone() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer =
+        litho::MediaWriter::new(&temp_path_buf)?.with_timezone("America/New_York".parse()?);
+    // the evening of October 2nd in New York is already the 3rd in UTC
+    let mut media = init_media(&server, None);
+    media.truncate(1);
+    media[0].media_metadata.creation_time = String::from("2014-10-03T01:30:00Z");
+    assert_eq!(4, media_writer.write_media(media, 1)?);
+
+    let mut expected = temp_path_buf.clone();
+    expected.push("2014/10/02/test.jpg");
+    assert_write_media(&expected, binary_content);
+    let expected = litho::YearMonthDay {
+        year: 2014,
+        month: 10,
+        day: 2,
+    };
+    assert_eq!(Some(expected), media_writer.most_recent_date());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_same_filename() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _first = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/123=d");
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+    let _second = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/456=d");
+        then.status(200).body(b"\xde\xad\xbe\xef");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let result = media_writer.write_media(init_same_filename(&server), 2);
     assert_eq!(8, result.unwrap());
 
-    let mut path_buf_test = temp_path_buf.clone();
-    path_buf_test.push("2014/10/02/0%2F1.jpg");
-    // println!("path={:?}", path_buf_test.as_path());
-    assert_write_media(&path_buf_test, &binary_content);
+    let day = temp_path_buf.join("2014/10/02");
+    assert_write_media(&day.join("IMG_0001.JPG"), b"\xca\xfe\xba\xbe");
+    assert_write_media(&day.join("IMG_0001~2.JPG"), b"\xde\xad\xbe\xef");
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(
+        PathBuf::from("2014/10/02/IMG_0001~2.JPG"),
+        index.get("def456").unwrap().path
+    );
 
-    let mut path_buf_camping = temp_path_buf.clone();
-    path_buf_camping.push("2014/10/03/camping.jpg");
-    assert_write_media(&path_buf_camping, &binary_content);
+    // a later run finds each where it was put
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let result = media_writer.write_media(init_same_filename(&server), 2);
+    assert_eq!(0, result.unwrap());
+    assert!(!day.join("IMG_0001~3.JPG").exists());
     Ok(())
 }
 
 #[test]
-fn test_write_media_when_album_has_more() -> Result<(), Box<dyn std::error::Error>> {
+fn test_write_media_collision_policies() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _first = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/123=d");
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+    let _second = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/456=d");
+        then.status(200).body(b"\xde\xad\xbe\xef");
+    });
+
+    for policy in litho::CollisionPolicy::VARIANTS {
+        let temp_dir = tempdir()?;
+        let temp_path_buf = PathBuf::from(temp_dir.path());
+        let media_writer = litho::MediaWriter::new(&temp_path_buf)?
+            .with_collision_policy(policy.parse()?)
+            .with_json_sidecar(true);
+        let mut media = init_same_filename(&server);
+        let second = media.pop().unwrap();
+        media_writer.write_media(media, 1)?;
+
+        // known from its sidecar alone, as if written by an older litho
+        fs::remove_dir_all(temp_path_buf.join(".litho"))?;
+        let media_writer =
+            litho::MediaWriter::new(&temp_path_buf)?.with_collision_policy(policy.parse()?);
+        media_writer.write_media(vec![second], 1)?;
+
+        let day = temp_path_buf.join("2014/10/02");
+        let expected: &[(&str, &[u8; 4])] = match *policy {
+            "suffix" => &[
+                ("IMG_0001.JPG", b"\xca\xfe\xba\xbe"),
+                ("IMG_0001~2.JPG", b"\xde\xad\xbe\xef"),
+            ],
+            "skip" => &[("IMG_0001.JPG", b"\xca\xfe\xba\xbe")],
+            "overwrite" => &[("IMG_0001.JPG", b"\xde\xad\xbe\xef")],
+            "id-in-name" => &[
+                ("IMG_0001.JPG", b"\xca\xfe\xba\xbe"),
+                ("IMG_0001_def456.JPG", b"\xde\xad\xbe\xef"),
+            ],
+            _ => unreachable!(),
+        };
+        let mut media_files: Vec<String> = fs::read_dir(&day)?
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| !name.ends_with(".json"))
+            .collect();
+        media_files.sort();
+        let expected_files: Vec<&str> = expected.iter().map(|(name, _)| *name).collect();
+        assert_eq!(expected_files, media_files, "{}", policy);
+        for (name, content) in expected {
+            assert_write_media(&day.join(name), content);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_media_overwrite_indexed() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _first = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/123=d");
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+    let second_mock = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/456=d");
+        then.status(200).body(b"\xde\xad\xbe\xef");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?
+        .with_collision_policy(litho::CollisionPolicy::Overwrite);
+    let mut media = init_same_filename(&server);
+    let second = media.pop().unwrap();
+    media_writer.write_media(media, 1)?;
+    media_writer.write_media(vec![second], 1)?;
+    assert_write_media(
+        &temp_path_buf.join("2014/10/02/IMG_0001.JPG"),
+        b"\xde\xad\xbe\xef",
+    );
+
+    // the first is no longer taken to be at the path it was written to
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(
+        litho::DownloadStatus::Replaced,
+        index.get("abc123").unwrap().status
+    );
+    assert_eq!(
+        litho::DownloadStatus::Downloaded,
+        index.get("def456").unwrap().status
+    );
+    assert!(index.verify(&temp_path_buf)?.is_empty());
+
+    // and a later run leaves the two of them as they are
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?
+        .with_collision_policy(litho::CollisionPolicy::Overwrite);
+    let media = init_same_filename(&server);
+    assert!(!media_writer.is_downloaded(&media[0]));
+    assert_eq!(0, media_writer.write_media(media, 2)?);
+    second_mock.assert_hits(1);
+    assert_write_media(
+        &temp_path_buf.join("2014/10/02/IMG_0001.JPG"),
+        b"\xde\xad\xbe\xef",
+    );
+    Ok(())
+}
+
+#[test]
+fn test_write_media_filtered() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let _mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(b"\xca\xfe\xba\xbe");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_filtered(true);
+    media_writer.write_media(init_media(&server, None), 2)?;
+    let expected = litho::YearMonthDay {
+        year: 2014,
+        month: 10,
+        day: 3,
+    };
+    assert_eq!(Some(expected), media_writer.most_recent_date());
+
+    // an unfiltered sync can't skip what the filters left out
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    assert_eq!(None, media_writer.most_recent_date());
+    Ok(())
+}
+
+#[test]
+fn test_write_missing() -> Result<(), Box<dyn std::error::Error>> {
     let server = MockServer::start();
     let binary_content = b"\xca\xfe\xba\xbe";
 
@@ -419,21 +1992,242 @@ fn test_write_media_when_album_has_more() -> Result<(), Box<dyn std::error::Erro
 
     let temp_dir = tempdir()?;
     let temp_path_buf = PathBuf::from(temp_dir.path());
-    let media_writer = litho::MediaWriter::new(&temp_path_buf);
-    let media = init_media(&server, None);
-    let result = media_writer.write_media(media, 1);
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let mut media = init_media(&server, None);
+    let camping = media.pop().unwrap();
+    assert_eq!(4, media_writer.write_media(media, 1).unwrap());
+    assert_eq!(1, media_writer.taken());
 
-    mock.assert();
-    assert_eq!(4, result.unwrap());
+    // the limit only counts the media that were missing
+    let (tx, rx) = mpsc::channel();
+    tx.send(init_media(&server, None))?;
+    drop(tx);
+    assert_eq!(4, media_writer.write_missing(rx, 1).unwrap());
+    assert!(media_writer.is_downloaded(&camping));
+    assert_eq!(2, media_writer.taken());
+
+    mock.assert_hits(2);
+    let mut path_buf_camping = temp_path_buf.clone();
+    path_buf_camping.push("2014/10/03/camping.jpg");
+    assert_write_media(&path_buf_camping, binary_content);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_bad_download() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(404).body("gone");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let result = media_writer.write_media(init_media(&server, None), 2);
 
+    mock.assert_hits(2);
+    assert!(matches!(result, Err(litho::Error::Status { .. })));
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(
+        litho::DownloadStatus::Failed,
+        index.get("abc123").unwrap().status
+    );
+    assert_eq!(
+        litho::DownloadStatus::Failed,
+        index.get("def456").unwrap().status
+    );
     let mut path_buf_test = temp_path_buf.clone();
     path_buf_test.push("2014/10/02/test.jpg");
-    // println!("path={:?}", path_buf_test.as_path());
-    assert_write_media(&path_buf_test, &binary_content);
+    assert!(!path_buf_test.as_path().exists());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_retries() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(503).body("unavailable");
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer =
+        litho::MediaWriter::new(&temp_path_buf)?.with_retry_policy(init_retry_policy());
+    let result = media_writer.write_media(init_media(&server, None), 1);
+
+    mock.assert_hits(3);
+    assert!(matches!(result, Err(litho::Error::Status { .. })));
+    Ok(())
+}
+
+#[test]
+fn test_write_media_retries_interrupted_download() -> Result<(), Box<dyn std::error::Error>> {
+    // the first response promises more than it sends before hanging up
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+    let server = thread::spawn(move || {
+        let bodies = [(11, "cut"), (11, "whole photo")];
+        for (content_length, body) in bodies {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                content_length, body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let media = litho::Media {
+        id: String::from("abc123"),
+        media_metadata: litho::MediaMetadata {
+            creation_time: String::from("2014-10-02T15:01:23Z"),
+            ..Default::default()
+        },
+        mime_type: String::from("image/jpeg"),
+        base_url: format!("http://{}/v1/mediaItems/123", address),
+        filename: String::from("test.jpg"),
+        ..Default::default()
+    };
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer =
+        litho::MediaWriter::new(&temp_path_buf)?.with_retry_policy(init_retry_policy());
+    media_writer.write_media(vec![media], 1)?;
+    server.join().unwrap();
+
+    let written = fs::read_to_string(temp_path_buf.join("2014/10/02/test.jpg"))?;
+    assert_eq!("whole photo", written);
+    let index = litho::SyncIndex::open(&temp_path_buf)?;
+    assert_eq!(
+        litho::DownloadStatus::Downloaded,
+        index.get("abc123").unwrap().status
+    );
+    Ok(())
+}
+
+#[test]
+fn test_write_media_jobs() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let mut media = Vec::new();
+    for i in 0..10 {
+        media.push(litho::Media {
+            id: format!("id{}", i),
+            media_metadata: litho::MediaMetadata {
+                creation_time: String::from("2014-10-02T15:01:23.045123456Z"),
+                ..Default::default()
+            },
+            mime_type: String::from("image/jpeg"),
+            base_url: server.url(format!("/v1/mediaItems/{}", i)),
+            filename: format!("{}.jpg", i),
+            ..Default::default()
+        });
+    }
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_jobs(4);
+    let result = media_writer.write_media(media, 8);
+
+    mock.assert_hits(8);
+    assert_eq!(32, result?);
+    for i in 0..8 {
+        let mut path_buf = temp_path_buf.clone();
+        path_buf.push(format!("2014/10/02/{}.jpg", i));
+        assert_write_media(&path_buf, binary_content);
+    }
+    let mut path_buf_skipped = temp_path_buf.clone();
+    path_buf_skipped.push("2014/10/02/8.jpg");
+    assert!(!path_buf_skipped.as_path().exists());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_continues_after_failure() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock_missing = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/123=d");
+        then.status(404).body("gone");
+    });
+    let mock_found = server.mock(|when, then| {
+        when.method(GET).path("/v1/mediaItems/456=d");
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?.with_jobs(2);
+    let result = media_writer.write_media(init_media(&server, None), 2);
 
+    mock_missing.assert();
+    mock_found.assert();
+    assert!(matches!(result, Err(litho::Error::Status { .. })));
     let mut path_buf_camping = temp_path_buf.clone();
     path_buf_camping.push("2014/10/03/camping.jpg");
-    assert!(!path_buf_camping.as_path().exists());
+    assert_write_media(&path_buf_camping, binary_content);
+    Ok(())
+}
+
+#[test]
+fn test_write_media_removes_partial_downloads() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let mut day = temp_path_buf.clone();
+    day.push("2014/10/02");
+    fs::create_dir_all(&day)?;
+    fs::write(day.join(".test.jpg.litho-part"), b"\xca\xfe")?;
+    fs::write(day.join("camping.jpg"), b"\xca\xfe")?;
+
+    litho::MediaWriter::new(&temp_path_buf)?;
+
+    assert!(!day.join(".test.jpg.litho-part").exists());
+    assert!(day.join("camping.jpg").exists());
+    Ok(())
+}
+
+#[test]
+fn test_write_media_replaces_empty_file() -> Result<(), Box<dyn std::error::Error>> {
+    let server = MockServer::start();
+    let binary_content = b"\xca\xfe\xba\xbe";
+
+    let mock = server.mock(|when, then| {
+        when.method(GET)
+            .path_matches(Regex::new(r#"/v1/mediaItems/.*"#).unwrap());
+        then.status(200).body(binary_content);
+    });
+
+    let temp_dir = tempdir()?;
+    let temp_path_buf = PathBuf::from(temp_dir.path());
+    let mut path_buf_test = temp_path_buf.clone();
+    path_buf_test.push("2014/10/02/test.jpg");
+    fs::create_dir_all(path_buf_test.parent().unwrap())?;
+    File::create(&path_buf_test)?;
+
+    let media_writer = litho::MediaWriter::new(&temp_path_buf)?;
+    let result = media_writer.write_media(init_media(&server, None), 1);
+
+    mock.assert_hits(1);
+    assert_eq!(4, result?);
+    assert_write_media(&path_buf_test, binary_content);
+    let partial = path_buf_test.with_file_name(".test.jpg.litho-part");
+    assert!(!partial.exists());
     Ok(())
 }
 
@@ -452,6 +2246,7 @@ fn init_media(server: &MockServer, filename: Option<&str>) -> Vec<litho::Media>
     let base_url_test = server.url("/v1/mediaItems/123");
     let metadata_test = litho::MediaMetadata {
         creation_time: String::from("2014-10-02T15:01:23.045123456Z"),
+        ..Default::default()
     };
     let name = filename.unwrap_or("test.jpg");
     let test_pic = litho::Media {
@@ -460,20 +2255,75 @@ fn init_media(server: &MockServer, filename: Option<&str>) -> Vec<litho::Media>
         mime_type: String::from("image/jpeg"),
         base_url: base_url_test.clone(),
         filename: String::from(name),
+        ..Default::default()
     };
 
     let base_url_camping = server.url("/v1/mediaItems/456");
     let metadata_camping = litho::MediaMetadata {
         creation_time: String::from("2014-10-03T15:01:23.045123456Z"),
+        ..Default::default()
     };
     let camping_pic = litho::Media {
-        id: String::from("abc123"),
+        id: String::from("def456"),
         media_metadata: metadata_camping,
         mime_type: String::from("image/jpeg"),
         base_url: base_url_camping.clone(),
         filename: String::from("camping.jpg"),
+        ..Default::default()
     };
     media_items.push(test_pic);
     media_items.push(camping_pic);
     media_items
 }
+
+fn init_video(server: &MockServer, status: &str) -> litho::Media {
+    let media = json!({
+        "id": "ghi789",
+        "baseUrl": server.url("/v1/mediaItems/789"),
+        "mimeType": "video/mp4",
+        "mediaMetadata": {
+            "creationTime": "2014-10-01T15:01:23Z",
+            "video": {
+                "status": status
+            }
+        },
+        "filename": "clip.jpg"
+    });
+    serde_json::from_value(media).unwrap()
+}
+
+/// Two photos taken on the same day by different phones with the same name.
+fn init_same_filename(server: &MockServer) -> Vec<litho::Media> {
+    let mut media = init_media(server, Some("IMG_0001.JPG"));
+    media[1].filename = String::from("IMG_0001.JPG");
+    media[1].media_metadata.creation_time = media[0].media_metadata.creation_time.clone();
+    media
+}
+
+fn init_start() -> litho::YearMonthDay {
+    litho::YearMonthDay {
+        year: 2022,
+        month: 9,
+        day: 1,
+    }
+}
+
+fn init_end() -> litho::YearMonthDay {
+    litho::YearMonthDay {
+        year: 2022,
+        month: 9,
+        day: 22,
+    }
+}
+
+fn init_ranges() -> Vec<litho::DateRange> {
+    vec![litho::DateRange::new(init_start(), init_end())]
+}
+
+fn init_retry_policy() -> litho::RetryPolicy {
+    litho::RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(10),
+    }
+}